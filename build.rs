@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// Writes `embedded.rs` with an `include_bytes!` entry for every file under `resources/`
+fn main() -> io::Result<()> {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let resources = manifest_dir.join("resources");
+    // Cargo scans the whole directory, so added and edited files both rebuild
+    println!("cargo:rerun-if-changed={}", resources.display());
+
+    let mut files = Vec::new();
+    collect(&resources, &mut files)?;
+    files.sort();
+
+    let mut source = String::from("&[\n");
+    for file in &files {
+        let name = file
+            .strip_prefix(&resources)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        source += &format!(
+            "    ({name:?}, include_bytes!({:?})),\n",
+            file.display().to_string()
+        );
+    }
+    source += "]\n";
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out_dir.join("embedded.rs"), source)
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
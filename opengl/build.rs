@@ -8,10 +8,10 @@ fn main() {
 
     Registry::new(
         gl_generator::Api::Gl,
-        (4, 5),
+        (4, 6),
         gl_generator::Profile::Core,
         gl_generator::Fallbacks::All,
-        [],
+        ["GL_ARB_gl_spirv"],
     )
     .write_bindings(GlobalGenerator, &mut file)
     .unwrap();
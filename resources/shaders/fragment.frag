@@ -1,12 +1,190 @@
 #version 450 core
 
+#ifndef MAX_DIRECTIONAL_LIGHTS
+#define MAX_DIRECTIONAL_LIGHTS 4
+#endif
+#ifndef MAX_POINT_LIGHTS
+#define MAX_POINT_LIGHTS 16
+#endif
+#ifndef MAX_SPOT_LIGHTS
+#define MAX_SPOT_LIGHTS 8
+#endif
+#ifndef MAX_CASCADES
+#define MAX_CASCADES 4
+#endif
+
 out vec4 FragColor;
 in vec2 TexCoord;
+in vec3 FragPos;
+in vec3 Normal;
+in float ViewDepth;
 
 layout (binding = 0) uniform sampler2D ourTexture;
 layout (binding = 1) uniform sampler2D ourFace;
+layout (binding = 2) uniform sampler2DArrayShadow directionalShadow;
+layout (binding = 3) uniform samplerCubeShadow pointShadow;
+
+uniform float shininess;
+uniform float specularStrength;
+
+struct DirectionalLight {
+    vec4 direction;
+    vec4 color;
+};
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    // constant, linear, quadratic
+    vec4 attenuation;
+};
+
+struct SpotLight {
+    vec4 position;
+    vec4 direction;
+    vec4 color;
+    vec4 attenuation;
+    // cosines of the inner and outer cutoff angles
+    vec4 cutoff;
+};
+
+layout(std140, binding = 1) uniform LightBlock {
+    DirectionalLight directionalLights[MAX_DIRECTIONAL_LIGHTS];
+    PointLight pointLights[MAX_POINT_LIGHTS];
+    SpotLight spotLights[MAX_SPOT_LIGHTS];
+    // directional, point, spot
+    uvec4 lightCounts;
+    vec4 ambient;
+    vec4 viewPosition;
+};
+
+layout(std140, binding = 2) uniform ShadowBlock {
+    mat4 cascadeMatrices[MAX_CASCADES];
+    // view depth where every cascade ends
+    vec4 cascadeSplits;
+    // bias, slope bias, PCF radius in texels, cascade count (0 without a shadowed light)
+    vec4 directionalShadowParams;
+    // xyz position, w far plane
+    vec4 pointShadowPosition;
+    // bias, PCF disk radius, enabled
+    vec4 pointShadowParams;
+};
+
+// Shadow of the first directional light, 1.0 is fully lit
+float directionalShadowFactor(vec3 normal, vec3 lightDir)
+{
+    int count = int(directionalShadowParams.w);
+    if (count == 0 || ViewDepth > cascadeSplits[count - 1]) {
+        return 1.0;
+    }
+
+    int cascade = 0;
+    while (cascade < count - 1 && ViewDepth > cascadeSplits[cascade]) {
+        cascade++;
+    }
+
+    vec4 lightSpace = cascadeMatrices[cascade] * vec4(FragPos, 1.0);
+    vec3 coords = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    float bias = max(directionalShadowParams.y * (1.0 - dot(normal, lightDir)), directionalShadowParams.x);
+    vec2 texel = 1.0 / vec2(textureSize(directionalShadow, 0).xy);
+    int radius = int(directionalShadowParams.z);
+
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 uv = coords.xy + vec2(x, y) * texel;
+            lit += texture(directionalShadow, vec4(uv, float(cascade), coords.z - bias));
+        }
+    }
+
+    float samples = float((2 * radius + 1) * (2 * radius + 1));
+    return lit / samples;
+}
+
+// Shadow of the first point light, 1.0 is fully lit
+float pointShadowFactor()
+{
+    if (pointShadowParams.z == 0.0) {
+        return 1.0;
+    }
+
+    vec3 fromLight = FragPos - pointShadowPosition.xyz;
+    float depth = length(fromLight) / pointShadowPosition.w;
+    if (depth > 1.0) {
+        return 1.0;
+    }
+
+    // The sample directions spread with the distance, so the disk is scaled by it
+    float radius = pointShadowParams.y * length(fromLight);
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            for (int z = -1; z <= 1; z++) {
+                vec3 direction = fromLight + vec3(x, y, z) * radius;
+                lit += texture(pointShadow, vec4(direction, depth - pointShadowParams.x));
+            }
+        }
+    }
+
+    return lit / 27.0;
+}
+
+vec3 blinnPhong(vec3 lightDir, vec3 radiance, vec3 normal, vec3 viewDir, vec3 albedo)
+{
+    float diffuse = max(dot(normal, lightDir), 0.0);
+    vec3 halfway = normalize(lightDir + viewDir);
+    float specular = diffuse > 0.0 ? pow(max(dot(normal, halfway), 0.0), shininess) : 0.0;
+
+    return radiance * (diffuse * albedo + specular * specularStrength);
+}
+
+float attenuate(vec4 attenuation, float distance)
+{
+    return 1.0 / (attenuation.x + attenuation.y * distance + attenuation.z * distance * distance);
+}
 
 void main()
 {
-    FragColor = mix(texture(ourTexture, TexCoord), texture(ourFace, vec2(TexCoord.x, TexCoord.y * -1.0)), 0.2);
-}
\ No newline at end of file
+    vec4 base = mix(texture(ourTexture, TexCoord), texture(ourFace, vec2(TexCoord.x, TexCoord.y * -1.0)), 0.2);
+    vec3 albedo = base.rgb;
+
+    vec3 normal = normalize(Normal);
+    vec3 viewDir = normalize(viewPosition.xyz - FragPos);
+
+    vec3 color = ambient.rgb * albedo;
+
+    for (uint i = 0; i < lightCounts.x; i++) {
+        vec3 lightDir = normalize(-directionalLights[i].direction.xyz);
+        float shadow = i == 0u ? directionalShadowFactor(normal, lightDir) : 1.0;
+        color += shadow * blinnPhong(lightDir, directionalLights[i].color.rgb, normal, viewDir, albedo);
+    }
+
+    for (uint i = 0; i < lightCounts.y; i++) {
+        vec3 toLight = pointLights[i].position.xyz - FragPos;
+        float distance = length(toLight);
+        vec3 radiance = pointLights[i].color.rgb * attenuate(pointLights[i].attenuation, distance);
+        float shadow = i == 0u ? pointShadowFactor() : 1.0;
+        color += shadow * blinnPhong(toLight / distance, radiance, normal, viewDir, albedo);
+    }
+
+    for (uint i = 0; i < lightCounts.z; i++) {
+        vec3 toLight = spotLights[i].position.xyz - FragPos;
+        float distance = length(toLight);
+        vec3 lightDir = toLight / distance;
+
+        float theta = dot(lightDir, normalize(-spotLights[i].direction.xyz));
+        float inner = spotLights[i].cutoff.x;
+        float outer = spotLights[i].cutoff.y;
+        float cone = clamp((theta - outer) / (inner - outer), 0.0, 1.0);
+
+        vec3 radiance = spotLights[i].color.rgb * attenuate(spotLights[i].attenuation, distance) * cone;
+        color += blinnPhong(lightDir, radiance, normal, viewDir, albedo);
+    }
+
+    FragColor = vec4(color, base.a);
+}
@@ -0,0 +1,63 @@
+#version 450 core
+
+// Geometry pass of the deferred renderer, takes the same material inputs as pbr.frag and stores
+// the surface instead of shading it
+
+layout (location = 0) out vec4 gAlbedo;
+layout (location = 1) out vec4 gNormal;
+layout (location = 2) out vec4 gMaterial;
+layout (location = 3) out vec4 gEmissive;
+
+in vec2 TexCoord;
+in vec3 FragPos;
+in vec3 Normal;
+
+// sRGB texture, so sampling returns linear colors
+layout (binding = 0) uniform sampler2D albedoMap;
+// Roughness in green, metallic in blue
+layout (binding = 1) uniform sampler2D metallicRoughnessMap;
+layout (binding = 2) uniform sampler2D normalMap;
+layout (binding = 3) uniform sampler2D occlusionMap;
+layout (binding = 4) uniform sampler2D emissiveMap;
+
+uniform vec4 albedoFactor;
+uniform float metallicFactor;
+uniform float roughnessFactor;
+uniform float normalScale;
+uniform float occlusionStrength;
+uniform vec3 emissiveFactor;
+
+// Builds the tangent frame from screen space derivatives, so the vertices need no tangents
+vec3 perturbNormal(vec3 normal)
+{
+    vec3 tangentNormal = texture(normalMap, TexCoord).xyz * 2.0 - 1.0;
+    tangentNormal.xy *= normalScale;
+
+    vec3 dp1 = dFdx(FragPos);
+    vec3 dp2 = dFdy(FragPos);
+    vec2 duv1 = dFdx(TexCoord);
+    vec2 duv2 = dFdy(TexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float scale = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    mat3 tbn = mat3(tangent * scale, bitangent * scale, normal);
+
+    return normalize(tbn * tangentNormal);
+}
+
+void main()
+{
+    vec2 metallicRoughness = texture(metallicRoughnessMap, TexCoord).bg;
+    float metallic = clamp(metallicRoughness.x * metallicFactor, 0.0, 1.0);
+    float roughness = clamp(metallicRoughness.y * roughnessFactor, 0.04, 1.0);
+    float occlusion = mix(1.0, texture(occlusionMap, TexCoord).r, occlusionStrength);
+
+    gAlbedo = vec4((texture(albedoMap, TexCoord) * albedoFactor).rgb, occlusion);
+    gNormal = vec4(perturbNormal(normalize(Normal)), 0.0);
+    gMaterial = vec4(metallic, roughness, 0.0, 0.0);
+    gEmissive = vec4(texture(emissiveMap, TexCoord).rgb * emissiveFactor, 0.0);
+}
@@ -0,0 +1,186 @@
+#version 450 core
+
+// Precomputes image based lighting, one permutation per pass:
+// EQUIRECT_TO_CUBE, IRRADIANCE, PREFILTER, and the BRDF lookup table without any define
+
+in vec2 TexCoord;
+out vec4 FragColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 1024u;
+
+#if defined(EQUIRECT_TO_CUBE) || defined(IRRADIANCE) || defined(PREFILTER)
+uniform int face;
+
+// Direction through the texel at TexCoord of the cube map face being rendered
+vec3 faceDirection()
+{
+    vec2 st = TexCoord * 2.0 - 1.0;
+
+    switch (face) {
+        case 0: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2: return normalize(vec3(st.x, 1.0, st.y));
+        case 3: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+#endif
+
+#if defined(PREFILTER) || !(defined(EQUIRECT_TO_CUBE) || defined(IRRADIANCE))
+float radicalInverse(uint bits)
+{
+    return float(bitfieldReverse(bits)) * 2.3283064365386963e-10;
+}
+
+vec2 hammersley(uint i, uint count)
+{
+    return vec2(float(i) / float(count), radicalInverse(i));
+}
+
+vec3 importanceSampleGGX(vec2 xi, vec3 normal, float roughness)
+{
+    float a = roughness * roughness;
+
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+
+    vec3 halfway = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+
+    return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
+#endif
+
+#if defined(EQUIRECT_TO_CUBE)
+layout (binding = 0) uniform sampler2D equirectangularMap;
+
+void main()
+{
+    vec3 direction = faceDirection();
+    vec2 uv = vec2(atan(direction.z, direction.x) / (2.0 * PI), asin(direction.y) / PI) + 0.5;
+
+    // The first row of the image is the top of the sky
+    FragColor = vec4(texture(equirectangularMap, vec2(uv.x, 1.0 - uv.y)).rgb, 1.0);
+}
+
+#elif defined(IRRADIANCE)
+layout (binding = 0) uniform samplerCube environmentMap;
+
+void main()
+{
+    vec3 normal = faceDirection();
+    vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    vec3 irradiance = vec3(0.0);
+    float samples = 0.0;
+    float delta = 0.025;
+
+    for (float phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            vec3 tangentSample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangentSample.x * right + tangentSample.y * up + tangentSample.z * normal;
+
+            irradiance += texture(environmentMap, direction).rgb * cos(theta) * sin(theta);
+            samples += 1.0;
+        }
+    }
+
+    FragColor = vec4(PI * irradiance / samples, 1.0);
+}
+
+#elif defined(PREFILTER)
+layout (binding = 0) uniform samplerCube environmentMap;
+
+uniform float roughness;
+// Width of the base level of environmentMap
+uniform float environmentSize;
+
+float distributionGGX(float NdotH, float roughness)
+{
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denominator = NdotH * NdotH * (a2 - 1.0) + 1.0;
+
+    return a2 / (PI * denominator * denominator);
+}
+
+void main()
+{
+    // Assumes the view direction equals the normal, the usual split sum approximation
+    vec3 normal = faceDirection();
+
+    vec3 color = vec3(0.0);
+    float weight = 0.0;
+
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGGX(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        vec3 light = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+
+        float NdotL = max(dot(normal, light), 0.0);
+        if (NdotL > 0.0) {
+            // Sampling a blurrier mip for unlikely directions avoids bright dots
+            float NdotH = max(dot(normal, halfway), 0.0);
+            float pdf = distributionGGX(NdotH, roughness) / 4.0 + 0.0001;
+
+            float texelAngle = 4.0 * PI / (6.0 * environmentSize * environmentSize);
+            float sampleAngle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+            float level = roughness == 0.0 ? 0.0 : 0.5 * log2(sampleAngle / texelAngle);
+
+            color += textureLod(environmentMap, light, level).rgb * NdotL;
+            weight += NdotL;
+        }
+    }
+
+    FragColor = vec4(color / weight, 1.0);
+}
+
+#else
+float geometrySchlickGGX(float NdotX, float roughness)
+{
+    // k for image based lighting, analytic lights use (roughness + 1)^2 / 8
+    float k = roughness * roughness / 2.0;
+
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+// Scale and bias to F0 of the specular response, indexed by NdotV and roughness
+void main()
+{
+    float NdotV = max(TexCoord.x, 0.0001);
+    float roughness = TexCoord.y;
+
+    vec3 view = vec3(sqrt(1.0 - NdotV * NdotV), 0.0, NdotV);
+    vec3 normal = vec3(0.0, 0.0, 1.0);
+
+    float scale = 0.0;
+    float bias = 0.0;
+
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGGX(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+
+        float NdotL = max(light.z, 0.0);
+        float NdotH = max(halfway.z, 0.0);
+        float VdotH = max(dot(view, halfway), 0.0);
+
+        if (NdotL > 0.0) {
+            float geometry = geometrySchlickGGX(NdotV, roughness) * geometrySchlickGGX(NdotL, roughness);
+            float visibility = geometry * VdotH / (NdotH * NdotV);
+            float fresnel = pow(1.0 - VdotH, 5.0);
+
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    FragColor = vec4(scale / float(SAMPLE_COUNT), bias / float(SAMPLE_COUNT), 0.0, 1.0);
+}
+#endif
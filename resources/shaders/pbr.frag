@@ -0,0 +1,415 @@
+#version 450 core
+
+// Metallic-roughness shading with the glTF texture conventions, lit by the lights in
+// LightBlock and an image based environment. With CLUSTERED the point lights come from the
+// light lists cluster_lights.comp built for the cluster of the fragment instead. The first
+// directional and point light are shadowed by the maps in ShadowBlock, like in fragment.frag.
+// With DEFERRED it is the lighting pass of the deferred renderer, a fullscreen triangle reading
+// the surface from the G-buffer gbuffer.frag filled instead of the material textures
+
+#ifndef MAX_DIRECTIONAL_LIGHTS
+#define MAX_DIRECTIONAL_LIGHTS 4
+#endif
+#ifndef MAX_POINT_LIGHTS
+#define MAX_POINT_LIGHTS 16
+#endif
+#ifndef MAX_SPOT_LIGHTS
+#define MAX_SPOT_LIGHTS 8
+#endif
+#ifndef MAX_CASCADES
+#define MAX_CASCADES 4
+#endif
+
+out vec4 FragColor;
+in vec2 TexCoord;
+
+#ifdef DEFERRED
+// Albedo with occlusion in alpha
+layout (binding = 0) uniform sampler2D gAlbedo;
+layout (binding = 1) uniform sampler2D gNormal;
+// Metallic, roughness
+layout (binding = 2) uniform sampler2D gMaterial;
+layout (binding = 3) uniform sampler2D gEmissive;
+layout (binding = 4) uniform sampler2D gDepth;
+
+uniform mat4 inverseViewProjection;
+// Only for the view depth that picks the shadow cascade
+uniform mat4 view;
+#else
+in vec3 FragPos;
+in vec3 Normal;
+in float ViewDepth;
+
+// sRGB texture, so sampling returns linear colors
+layout (binding = 0) uniform sampler2D albedoMap;
+// Roughness in green, metallic in blue
+layout (binding = 1) uniform sampler2D metallicRoughnessMap;
+layout (binding = 2) uniform sampler2D normalMap;
+layout (binding = 3) uniform sampler2D occlusionMap;
+layout (binding = 4) uniform sampler2D emissiveMap;
+
+uniform vec4 albedoFactor;
+uniform float metallicFactor;
+uniform float roughnessFactor;
+uniform float normalScale;
+uniform float occlusionStrength;
+uniform vec3 emissiveFactor;
+#endif
+
+layout (binding = 5) uniform samplerCube irradianceMap;
+layout (binding = 6) uniform samplerCube prefilterMap;
+layout (binding = 7) uniform sampler2D brdfLut;
+
+layout (binding = 8) uniform sampler2DArrayShadow directionalShadow;
+layout (binding = 9) uniform samplerCubeShadow pointShadow;
+
+uniform float prefilterLevels;
+
+const float PI = 3.14159265359;
+
+struct DirectionalLight {
+    vec4 direction;
+    vec4 color;
+};
+
+struct PointLight {
+    vec4 position;
+    vec4 color;
+    // constant, linear, quadratic
+    vec4 attenuation;
+};
+
+struct SpotLight {
+    vec4 position;
+    vec4 direction;
+    vec4 color;
+    vec4 attenuation;
+    // cosines of the inner and outer cutoff angles
+    vec4 cutoff;
+};
+
+// The ambient term is replaced by the environment
+layout(std140, binding = 1) uniform LightBlock {
+    DirectionalLight directionalLights[MAX_DIRECTIONAL_LIGHTS];
+    PointLight pointLights[MAX_POINT_LIGHTS];
+    SpotLight spotLights[MAX_SPOT_LIGHTS];
+    // directional, point, spot
+    uvec4 lightCounts;
+    vec4 ambient;
+    vec4 viewPosition;
+};
+
+layout(std140, binding = 2) uniform ShadowBlock {
+    mat4 cascadeMatrices[MAX_CASCADES];
+    // view depth where every cascade ends
+    vec4 cascadeSplits;
+    // bias, slope bias, PCF radius in texels, cascade count (0 without a shadowed light)
+    vec4 directionalShadowParams;
+    // xyz position, w far plane
+    vec4 pointShadowPosition;
+    // bias, PCF disk radius, enabled
+    vec4 pointShadowParams;
+};
+
+#ifdef CLUSTERED
+struct ClusterLight {
+    // xyz world position, w range
+    vec4 positionRange;
+    vec4 color;
+};
+
+layout(std430, binding = 0) buffer ClusterLightBuffer {
+    ClusterLight clusterLights[];
+};
+
+// Offset into clusterLightIndices and light count of every cluster
+layout(std430, binding = 2) buffer ClusterRangeBuffer {
+    uvec2 clusterRanges[];
+};
+
+layout(std430, binding = 3) buffer ClusterIndexBuffer {
+    uint clusterLightIndices[];
+};
+
+layout(std140, binding = 3) uniform ClusterBlock {
+    mat4 clusterView;
+    // tiles in x and y, depth slices, lights
+    uvec4 clusterCounts;
+    // near, far, framebuffer width and height
+    vec4 clusterParams;
+};
+
+// Same layout as ClusterGrid::index, with the slice from ClusterGrid::slice
+uint clusterIndex(float viewDepth)
+{
+    uvec2 tile = uvec2(gl_FragCoord.xy / clusterParams.zw * vec2(clusterCounts.xy));
+    tile = min(tile, clusterCounts.xy - uvec2(1u));
+
+    float near = clusterParams.x;
+    float far = clusterParams.y;
+    float slice = log(max(viewDepth, near) / near) / log(far / near) * float(clusterCounts.z);
+    uint z = min(uint(slice), clusterCounts.z - 1u);
+
+    return tile.x + clusterCounts.x * (tile.y + clusterCounts.y * z);
+}
+
+// Inverse square falloff windowed to reach zero at the range, so the culling is exact
+float clusterFalloff(float distance, float range)
+{
+    float ratio = distance / range;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+
+    return window * window / (distance * distance + 1.0);
+}
+#endif
+
+// Shadow of the first directional light, 1.0 is fully lit
+float directionalShadowFactor(vec3 fragPos, float viewDepth, vec3 normal, vec3 lightDir)
+{
+    int count = int(directionalShadowParams.w);
+    if (count == 0 || viewDepth > cascadeSplits[count - 1]) {
+        return 1.0;
+    }
+
+    int cascade = 0;
+    while (cascade < count - 1 && viewDepth > cascadeSplits[cascade]) {
+        cascade++;
+    }
+
+    vec4 lightSpace = cascadeMatrices[cascade] * vec4(fragPos, 1.0);
+    vec3 coords = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    float bias = max(directionalShadowParams.y * (1.0 - dot(normal, lightDir)), directionalShadowParams.x);
+    vec2 texel = 1.0 / vec2(textureSize(directionalShadow, 0).xy);
+    int radius = int(directionalShadowParams.z);
+
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 uv = coords.xy + vec2(x, y) * texel;
+            lit += texture(directionalShadow, vec4(uv, float(cascade), coords.z - bias));
+        }
+    }
+
+    float samples = float((2 * radius + 1) * (2 * radius + 1));
+    return lit / samples;
+}
+
+// Shadow of the first point light, 1.0 is fully lit
+float pointShadowFactor(vec3 fragPos)
+{
+    if (pointShadowParams.z == 0.0) {
+        return 1.0;
+    }
+
+    vec3 fromLight = fragPos - pointShadowPosition.xyz;
+    float depth = length(fromLight) / pointShadowPosition.w;
+    if (depth > 1.0) {
+        return 1.0;
+    }
+
+    // The sample directions spread with the distance, so the disk is scaled by it
+    float radius = pointShadowParams.y * length(fromLight);
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            for (int z = -1; z <= 1; z++) {
+                vec3 direction = fromLight + vec3(x, y, z) * radius;
+                lit += texture(pointShadow, vec4(direction, depth - pointShadowParams.x));
+            }
+        }
+    }
+
+    return lit / 27.0;
+}
+
+#ifndef DEFERRED
+// Builds the tangent frame from screen space derivatives, so the vertices need no tangents
+vec3 perturbNormal(vec3 normal)
+{
+    vec3 tangentNormal = texture(normalMap, TexCoord).xyz * 2.0 - 1.0;
+    tangentNormal.xy *= normalScale;
+
+    vec3 dp1 = dFdx(FragPos);
+    vec3 dp2 = dFdy(FragPos);
+    vec2 duv1 = dFdx(TexCoord);
+    vec2 duv2 = dFdy(TexCoord);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float scale = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    mat3 tbn = mat3(tangent * scale, bitangent * scale, normal);
+
+    return normalize(tbn * tangentNormal);
+}
+#endif
+
+float distributionGGX(float NdotH, float roughness)
+{
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denominator = NdotH * NdotH * (a2 - 1.0) + 1.0;
+
+    return a2 / (PI * denominator * denominator);
+}
+
+float geometrySchlickGGX(float NdotX, float roughness)
+{
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+
+    return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+float geometrySmith(float NdotV, float NdotL, float roughness)
+{
+    return geometrySchlickGGX(NdotV, roughness) * geometrySchlickGGX(NdotL, roughness);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0)
+{
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 F0, float roughness)
+{
+    return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance for one light arriving from lightDir
+vec3 cookTorrance(vec3 lightDir, vec3 radiance, vec3 normal, vec3 viewDir, vec3 albedo, float metallic, float roughness, vec3 F0)
+{
+    vec3 halfway = normalize(lightDir + viewDir);
+
+    float NdotL = max(dot(normal, lightDir), 0.0);
+    float NdotV = max(dot(normal, viewDir), 0.0);
+    float NdotH = max(dot(normal, halfway), 0.0);
+
+    float distribution = distributionGGX(NdotH, roughness);
+    float geometry = geometrySmith(NdotV, NdotL, roughness);
+    vec3 fresnel = fresnelSchlick(max(dot(halfway, viewDir), 0.0), F0);
+
+    vec3 specular = distribution * geometry * fresnel / (4.0 * NdotV * NdotL + 0.0001);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
+
+    return (diffuse + specular) * radiance * NdotL;
+}
+
+float attenuate(vec4 attenuation, float distance)
+{
+    return 1.0 / (attenuation.x + attenuation.y * distance + attenuation.z * distance * distance);
+}
+
+void main()
+{
+#ifdef DEFERRED
+    float depth = texture(gDepth, TexCoord).r;
+    if (depth == 1.0) {
+        // Nothing was drawn here, keep the clear color
+        discard;
+    }
+
+    vec4 clip = inverseViewProjection * vec4(vec3(TexCoord, depth) * 2.0 - 1.0, 1.0);
+    vec3 fragPos = clip.xyz / clip.w;
+    float viewDepth = -(view * vec4(fragPos, 1.0)).z;
+
+    vec4 albedoOcclusion = texture(gAlbedo, TexCoord);
+    vec3 albedo = albedoOcclusion.rgb;
+    float alpha = 1.0;
+    float occlusion = albedoOcclusion.a;
+
+    vec2 metallicRoughness = texture(gMaterial, TexCoord).rg;
+    float metallic = metallicRoughness.x;
+    float roughness = metallicRoughness.y;
+
+    vec3 emissive = texture(gEmissive, TexCoord).rgb;
+    vec3 normal = normalize(texture(gNormal, TexCoord).xyz);
+#else
+    vec3 fragPos = FragPos;
+    float viewDepth = ViewDepth;
+
+    vec4 base = texture(albedoMap, TexCoord) * albedoFactor;
+    vec3 albedo = base.rgb;
+    float alpha = base.a;
+
+    vec2 metallicRoughness = texture(metallicRoughnessMap, TexCoord).bg;
+    float metallic = clamp(metallicRoughness.x * metallicFactor, 0.0, 1.0);
+    float roughness = clamp(metallicRoughness.y * roughnessFactor, 0.04, 1.0);
+
+    float occlusion = mix(1.0, texture(occlusionMap, TexCoord).r, occlusionStrength);
+    vec3 emissive = texture(emissiveMap, TexCoord).rgb * emissiveFactor;
+
+    vec3 normal = perturbNormal(normalize(Normal));
+#endif
+    vec3 viewDir = normalize(viewPosition.xyz - fragPos);
+    float NdotV = max(dot(normal, viewDir), 0.0);
+
+    // Dielectrics reflect about 4% head on, metals tint the reflection with their albedo
+    vec3 F0 = mix(vec3(0.04), albedo, metallic);
+
+    vec3 color = vec3(0.0);
+
+    for (uint i = 0u; i < lightCounts.x; i++) {
+        vec3 lightDir = normalize(-directionalLights[i].direction.xyz);
+        float shadow = i == 0u ? directionalShadowFactor(fragPos, viewDepth, normal, lightDir) : 1.0;
+        color += shadow * cookTorrance(lightDir, directionalLights[i].color.rgb, normal, viewDir, albedo, metallic, roughness, F0);
+    }
+
+#ifdef CLUSTERED
+    uvec2 range = clusterRanges[clusterIndex(viewDepth)];
+
+    for (uint i = 0u; i < range.y; i++) {
+        uint index = clusterLightIndices[range.x + i];
+        vec3 toLight = clusterLights[index].positionRange.xyz - fragPos;
+        float distance = length(toLight);
+        vec3 radiance = clusterLights[index].color.rgb * clusterFalloff(distance, clusterLights[index].positionRange.w);
+        color += cookTorrance(toLight / distance, radiance, normal, viewDir, albedo, metallic, roughness, F0);
+    }
+#else
+    for (uint i = 0u; i < lightCounts.y; i++) {
+        vec3 toLight = pointLights[i].position.xyz - fragPos;
+        float distance = length(toLight);
+        vec3 radiance = pointLights[i].color.rgb * attenuate(pointLights[i].attenuation, distance);
+        float shadow = i == 0u ? pointShadowFactor(fragPos) : 1.0;
+        color += shadow * cookTorrance(toLight / distance, radiance, normal, viewDir, albedo, metallic, roughness, F0);
+    }
+#endif
+
+    for (uint i = 0u; i < lightCounts.z; i++) {
+        vec3 toLight = spotLights[i].position.xyz - fragPos;
+        float distance = length(toLight);
+        vec3 lightDir = toLight / distance;
+
+        float theta = dot(lightDir, normalize(-spotLights[i].direction.xyz));
+        float inner = spotLights[i].cutoff.x;
+        float outer = spotLights[i].cutoff.y;
+        float cone = clamp((theta - outer) / (inner - outer), 0.0, 1.0);
+
+        vec3 radiance = spotLights[i].color.rgb * attenuate(spotLights[i].attenuation, distance) * cone;
+        color += cookTorrance(lightDir, radiance, normal, viewDir, albedo, metallic, roughness, F0);
+    }
+
+    // Split sum approximation of the environment lighting
+    vec3 fresnel = fresnelSchlickRoughness(NdotV, F0, roughness);
+    vec3 diffuse = texture(irradianceMap, normal).rgb * albedo * (1.0 - fresnel) * (1.0 - metallic);
+
+    vec3 reflection = reflect(-viewDir, normal);
+    vec3 prefiltered = textureLod(prefilterMap, reflection, roughness * (prefilterLevels - 1.0)).rgb;
+    vec2 brdf = texture(brdfLut, vec2(NdotV, roughness)).rg;
+    vec3 specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    color += (diffuse + specular) * occlusion + emissive;
+
+    // Reinhard tone mapping, then back to the gamma of the default framebuffer
+    color = color / (color + 1.0);
+    color = pow(color, vec3(1.0 / 2.2));
+
+    FragColor = vec4(color, alpha);
+}
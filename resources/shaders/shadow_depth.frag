@@ -0,0 +1,17 @@
+#version 450 core
+
+// Directional shadows only need the rasterized depth, point shadows store the distance to the
+// light so all six faces share one depth scale
+in vec3 FragPos;
+
+#ifdef POINT_SHADOW
+uniform vec3 lightPosition;
+uniform float farPlane;
+#endif
+
+void main()
+{
+#ifdef POINT_SHADOW
+    gl_FragDepth = length(FragPos - lightPosition) / farPlane;
+#endif
+}
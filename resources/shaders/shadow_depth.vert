@@ -0,0 +1,18 @@
+#version 450 core
+
+layout (location = 0) in vec3 aPos;
+
+out vec3 FragPos;
+
+layout(std140, binding = 0) uniform MatrixBlock {
+    mat4 projection;
+    mat4 view;
+    mat4 model;
+};
+
+void main()
+{
+    vec4 worldPos = model * vec4(aPos, 1.0);
+    gl_Position = projection * view * worldPos;
+    FragPos = worldPos.xyz;
+}
@@ -0,0 +1,17 @@
+#version 450 core
+
+in vec3 Direction;
+out vec4 FragColor;
+
+layout (binding = 0) uniform samplerCube skybox;
+
+void main()
+{
+    vec3 color = texture(skybox, Direction).rgb;
+
+    // The same tone mapping as pbr.frag, environments are HDR
+    color = color / (color + 1.0);
+    color = pow(color, vec3(1.0 / 2.2));
+
+    FragColor = vec4(color, 1.0);
+}
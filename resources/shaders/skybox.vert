@@ -0,0 +1,18 @@
+#version 450 core
+
+// One triangle covering the viewport on the far plane, with the view direction of every pixel
+out vec3 Direction;
+
+// Built from the view matrix without its translation, so the sky never gets closer
+uniform mat4 inverseViewProjection;
+
+void main()
+{
+    vec2 position = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+
+    vec4 direction = inverseViewProjection * vec4(position, 1.0, 1.0);
+    Direction = direction.xyz / direction.w;
+
+    // z equal to w puts every pixel at depth 1.0, behind anything drawn before
+    gl_Position = vec4(position, 1.0, 1.0);
+}
@@ -0,0 +1,38 @@
+#version 450 core
+
+// Textures from a tile set, one array layer per tile, lit by a fixed light from above. With
+// DEFERRED the surface goes into the G-buffer instead, and the lighting pass shades it
+#ifdef DEFERRED
+layout (location = 0) out vec4 gAlbedo;
+layout (location = 1) out vec4 gNormal;
+layout (location = 2) out vec4 gMaterial;
+layout (location = 3) out vec4 gEmissive;
+#else
+out vec4 FragColor;
+#endif
+
+in vec2 TexCoord;
+in vec3 Normal;
+
+layout (binding = 0) uniform sampler2DArray tiles;
+
+uniform uint tileId;
+
+void main()
+{
+    vec4 color = texture(tiles, vec3(TexCoord, float(tileId)));
+
+#ifdef DEFERRED
+    // The tile set isn't sRGB, so the colors are linearized by hand. Fully rough dielectric,
+    // without occlusion or emission
+    gAlbedo = vec4(pow(color.rgb, vec3(2.2)), 1.0);
+    gNormal = vec4(normalize(Normal), 0.0);
+    gMaterial = vec4(0.0, 1.0, 0.0, 0.0);
+    gEmissive = vec4(0.0);
+#else
+    vec3 lightDir = normalize(vec3(0.3, 1.0, 0.5));
+    float diffuse = max(dot(normalize(Normal), lightDir), 0.0);
+
+    FragColor = vec4(color.rgb * (0.4 + 0.6 * diffuse), color.a);
+#endif
+}
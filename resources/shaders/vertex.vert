@@ -2,8 +2,12 @@
 
 layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec2 aTexCoord;
+layout (location = 2) in vec3 aNormal;
 
 out vec2 TexCoord;
+out vec3 FragPos;
+out vec3 Normal;
+out float ViewDepth;
 
 layout(std140, binding = 0) uniform MatrixBlock {
     mat4 projection;
@@ -13,6 +17,10 @@ layout(std140, binding = 0) uniform MatrixBlock {
 
 void main()
 {
-    gl_Position = projection * view * model * vec4(aPos, 1.0);
+    vec4 worldPos = model * vec4(aPos, 1.0);
+    gl_Position = projection * view * worldPos;
     TexCoord = aTexCoord;
-}
\ No newline at end of file
+    FragPos = worldPos.xyz;
+    Normal = mat3(transpose(inverse(model))) * aNormal;
+    ViewDepth = -(view * worldPos).z;
+}
@@ -0,0 +1,170 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{env, fs};
+
+use image::codecs::hdr::HdrDecoder;
+use image::{DynamicImage, Rgb32FImage};
+
+const ASSETS_ENV: &str = "OPENGL_STUFFS_ASSETS";
+pub const ASSETS_FLAG: &str = "--assets";
+
+/// Copies of `resources/` compiled into the binary, used when nothing on disk overrides them.
+/// `build.rs` lists every file in the directory
+static EMBEDDED: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+static ASSETS: OnceLock<Assets> = OnceLock::new();
+
+/// Resolves paths relative to `resources/`, checking next to the executable, then the root given
+/// by `--assets <dir>` or `$OPENGL_STUFFS_ASSETS`, then the embedded copies
+pub struct Assets {
+    roots: Vec<PathBuf>,
+}
+
+#[allow(dead_code)]
+impl Assets {
+    pub fn new(roots: Vec<PathBuf>) -> Assets {
+        Assets { roots }
+    }
+
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> Assets {
+        let mut roots = Vec::new();
+
+        if let Some(exe_dir) = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        {
+            roots.push(exe_dir.join("resources"));
+        }
+
+        match assets_flag(args) {
+            Some(root) => roots.push(root),
+            None => roots.extend(env::var_os(ASSETS_ENV).map(PathBuf::from)),
+        }
+
+        Assets { roots }
+    }
+
+    /// The path on disk `path` resolves to, `None` if only the embedded copy exists
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            return path.exists().then(|| path.to_path_buf());
+        }
+
+        self.roots
+            .iter()
+            .map(|root| root.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    pub fn resolve_dir(&self, path: &str) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .map(|root| root.join(path))
+            .find(|candidate| candidate.is_dir())
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        if let Some(resolved) = self.resolve(path) {
+            return fs::read(resolved);
+        }
+
+        embedded(path).map(<[u8]>::to_vec).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Asset `{path}` not found in {:?} or the embedded resources",
+                    self.roots
+                ),
+            )
+        })
+    }
+
+    pub fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn image(&self, path: &str) -> image::ImageResult<DynamicImage> {
+        decode(path, &self.read(path)?)
+    }
+}
+
+/// Decodes `bytes` in the format of the extension of `path`. Radiance `.hdr` files keep their
+/// range as `ImageRgb32F`, the generic decoder would tone map them to 8 bits
+pub fn decode(path: &str, bytes: &[u8]) -> image::ImageResult<DynamicImage> {
+    let format = image::ImageFormat::from_path(path)?;
+    if format != image::ImageFormat::Hdr {
+        return image::load_from_memory_with_format(bytes, format);
+    }
+
+    let decoder = HdrDecoder::new(bytes)?;
+    let metadata = decoder.metadata();
+    let pixels: Vec<f32> = decoder
+        .read_image_hdr()?
+        .into_iter()
+        .flat_map(|pixel| pixel.0)
+        .collect();
+
+    let image = Rgb32FImage::from_raw(metadata.width, metadata.height, pixels)
+        .expect("the decoder returns width * height pixels");
+    Ok(DynamicImage::ImageRgb32F(image))
+}
+
+fn embedded(path: &str) -> Option<&'static [u8]> {
+    let path = path.trim_start_matches("./").replace('\\', "/");
+    EMBEDDED
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, bytes)| *bytes)
+}
+
+#[allow(dead_code)]
+pub fn embedded_files(prefix: &str) -> impl Iterator<Item = (&'static str, &'static [u8])> + '_ {
+    EMBEDDED
+        .iter()
+        .filter(move |(name, _)| name.starts_with(prefix))
+        .copied()
+}
+
+fn assets_flag(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == ASSETS_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(root) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix(ASSETS_FLAG)?.strip_prefix('='))
+        {
+            return Some(PathBuf::from(root));
+        }
+    }
+
+    None
+}
+
+/// Installs the global resolver, has no effect once any asset was loaded
+pub fn init(assets: Assets) {
+    let _ = ASSETS.set(assets);
+}
+
+pub fn get() -> &'static Assets {
+    ASSETS.get_or_init(|| Assets::from_args(env::args_os().skip(1)))
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    get().read(path)
+}
+
+pub fn read_to_string(path: &str) -> io::Result<String> {
+    get().read_to_string(path)
+}
+
+pub fn image(path: &str) -> image::ImageResult<DynamicImage> {
+    get().image(path)
+}
@@ -3,12 +3,18 @@ use std::{ffi::CString, mem, ptr};
 use nalgebra_glm::{Scalar, TMat};
 use opengl::gl;
 
-use crate::{shaders::Shader, size_of};
+use crate::{
+    shaders::{types, Shader},
+    size_of,
+};
 
 pub struct VertexBuilder<'a> {
     next_attribute: u32,
     last_size: u32,
     vao: &'a mut u32,
+    buffer: u32,
+    offset: isize,
+    attributes: Vec<(u32, gl::types::GLenum)>,
 }
 
 impl VertexBuilder<'_> {
@@ -21,7 +27,6 @@ impl VertexBuilder<'_> {
 
         unsafe {
             gl::CreateVertexArrays(1, vao);
-            gl::VertexArrayVertexBuffer(*vao, 0, buffer.0, size, 5 * size_of(gl::FLOAT) as i32);
             gl::VertexArrayElementBuffer(*vao, buffer.0);
         }
 
@@ -29,6 +34,9 @@ impl VertexBuilder<'_> {
             next_attribute: 0,
             last_size: 0,
             vao,
+            buffer: buffer.0,
+            offset: size,
+            attributes: Vec::new(),
         }
     }
 
@@ -47,9 +55,57 @@ impl VertexBuilder<'_> {
 
             self.last_size += size;
             self.next_attribute += 1;
+            self.attributes.push((size, _type));
+
+            // The vertices are interleaved, so the stride grows with every attribute
+            gl::VertexArrayVertexBuffer(
+                *self.vao,
+                0,
+                self.buffer,
+                self.offset,
+                (self.last_size * size_of(gl::FLOAT)) as i32,
+            );
             self
         }
     }
+
+    /// Panics if any vertex input of `shader` isn't fed by a matching attribute
+    pub fn validate(self, shader: &Shader) -> Self {
+        for input in &shader.reflection().inputs {
+            let Some(&(size, _type)) = self.attributes.get(input.location as usize) else {
+                panic!(
+                    "Vertex input `{}` at location {} has no attribute in the vertex layout",
+                    input.name, input.location
+                );
+            };
+
+            let Some(glsl) = types::glsl_type(input.gl_type) else {
+                panic!(
+                    "Vertex input `{}` has unsupported type {}",
+                    input.name,
+                    types::type_name(input.gl_type)
+                );
+            };
+
+            let matches_component = match glsl.component {
+                gl::FLOAT => _type == gl::FLOAT,
+                gl::INT => _type == gl::INT,
+                gl::UNSIGNED_INT => _type == gl::UNSIGNED_INT,
+                gl::DOUBLE => _type == gl::DOUBLE,
+                _ => false,
+            };
+
+            assert!(
+                matches_component && size as i32 <= glsl.components,
+                "Vertex input `{}` at location {} is {}, but the layout provides {size} x 0x{_type:04X}",
+                input.name,
+                input.location,
+                glsl.name
+            );
+        }
+
+        self
+    }
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -57,10 +113,16 @@ pub struct UBO {
     ubo: Buffer,
     offset: isize,
     size: isize,
+    binding: u32,
 }
 
+#[allow(dead_code)]
 impl UBO {
     pub fn new(size: usize) -> UBO {
+        UBO::with_binding(size, 0)
+    }
+
+    pub fn with_binding(size: usize, binding: u32) -> UBO {
         let size = size as isize;
         let ubo = Buffer::create(size);
 
@@ -68,6 +130,21 @@ impl UBO {
             ubo,
             offset: 0,
             size,
+            binding,
+        }
+    }
+
+    /// Writes `data` at `offset` bytes, independent of `next_attribute`
+    pub fn write<T>(&self, offset: isize, data: &[T]) {
+        let size = mem::size_of_val(data) as isize;
+
+        assert!(
+            size + offset <= self.size,
+            "Attributes larger than specified!"
+        );
+
+        unsafe {
+            gl::NamedBufferSubData(self.ubo.0, offset, size, data.as_ptr() as *const _);
         }
     }
 
@@ -83,12 +160,7 @@ impl UBO {
         );
 
         unsafe {
-            gl::BufferSubData(
-                gl::UNIFORM_BUFFER,
-                self.offset,
-                size,
-                data.as_ptr() as *const _,
-            );
+            gl::NamedBufferSubData(self.ubo.0, self.offset, size, data.as_ptr() as *const _);
         }
 
         self.offset += size;
@@ -105,12 +177,26 @@ impl UBO {
     }
 
     pub fn attach_new_shader(&self, shader: &Shader, ubo_name: &str) {
+        let Some(block) = shader.reflection().uniform_block(ubo_name) else {
+            panic!(
+                "Uniform block `{ubo_name}` is not active in program {}",
+                shader.id
+            );
+        };
+
+        assert!(
+            block.data_size as isize <= self.size,
+            "Uniform block `{ubo_name}` needs {} bytes, but the UBO only has {}",
+            block.data_size,
+            self.size
+        );
+
         let ubo_name = CString::new(ubo_name).unwrap();
         let index = shader.get_uniform_block_index(&ubo_name);
-        shader.uniform_block_binding(index);
+        shader.uniform_block_binding(index, self.binding);
 
         unsafe {
-            gl::BindBufferRange(gl::UNIFORM_BUFFER, index, self.ubo.0, 0, self.size);
+            gl::BindBufferRange(gl::UNIFORM_BUFFER, self.binding, self.ubo.0, 0, self.size);
         }
     }
 
@@ -127,6 +213,7 @@ impl UBO {
 
 pub struct Buffer(u32);
 
+#[allow(dead_code)]
 impl Buffer {
     pub fn create(size: isize) -> Buffer {
         let mut buffer = 0;
@@ -139,6 +226,33 @@ impl Buffer {
         Buffer(buffer)
     }
 
+    pub fn create_with_data<T>(data: &[T]) -> Buffer {
+        let size = mem::size_of_val(data) as isize;
+        let buffer = Buffer::create(size);
+
+        unsafe {
+            gl::NamedBufferSubData(buffer.0, 0, size, data.as_ptr() as *const _);
+        }
+
+        buffer
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+
+    /// Writes `data` at `offset` bytes, the buffer has to be large enough
+    pub fn write<T>(&self, offset: isize, data: &[T]) {
+        unsafe {
+            gl::NamedBufferSubData(
+                self.0,
+                offset,
+                mem::size_of_val(data) as isize,
+                data.as_ptr() as *const _,
+            );
+        }
+    }
+
     pub fn create_shared_buffer<A, B>(vertices: &[A], indices: &[B]) -> Buffer {
         let vrt_size = mem::size_of_val(vertices) as isize;
         let ind_size = mem::size_of_val(indices) as isize;
@@ -68,6 +68,14 @@ impl Camera {
         self.camera_front = glm::normalize(&self.direction);
     }
 
+    pub fn position(&self) -> Vec3 {
+        self.camera_pos
+    }
+
+    pub fn front(&self) -> Vec3 {
+        self.camera_front
+    }
+
     pub fn view(&mut self) -> glm::Mat4 {
         glm::look_at(
             &self.camera_pos,
@@ -76,3 +84,10 @@ impl Camera {
         )
     }
 }
+
+/// Near and far planes of a `glm::perspective` projection
+pub fn depth_range(projection: &glm::Mat4) -> (f32, f32) {
+    let a = projection[(2, 2)];
+    let b = projection[(2, 3)];
+    (b / (a - 1.0), b / (a + 1.0))
+}
@@ -0,0 +1,434 @@
+use std::mem;
+
+use nalgebra_glm::{self as glm, Mat4, Vec3};
+
+use crate::buffers::{Buffer, UBO};
+use crate::camera;
+use crate::compute::{ComputePipeline, MemoryBarrier};
+use crate::shaders::Shader;
+
+/// Must match the default in `cluster_lights.comp`, lights past it are dropped from a cluster
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 128;
+
+/// Binding point of `ClusterBlock`, after `MatrixBlock`, `LightBlock` and `ShadowBlock`
+pub const CLUSTER_BLOCK_BINDING: u32 = 3;
+
+/// Shader storage binding points, shared by `cluster_lights.comp` and the `CLUSTERED` variant
+/// of `pbr.frag`
+const LIGHTS_BINDING: u32 = 0;
+const BOUNDS_BINDING: u32 = 1;
+const RANGES_BINDING: u32 = 2;
+const INDICES_BINDING: u32 = 3;
+
+/// The view frustum split into screen tiles and exponentially spaced depth slices, so clusters
+/// far away are about as deep as they are wide
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClusterGrid {
+    pub tiles_x: u32,
+    pub tiles_y: u32,
+    pub slices: u32,
+    /// The clip planes of the projection, `all_bounds` and `ClusteredLights::update` take them
+    /// from the projection they get
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for ClusterGrid {
+    fn default() -> Self {
+        ClusterGrid {
+            tiles_x: 16,
+            tiles_y: 9,
+            slices: 24,
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+}
+
+/// An axis aligned box in view space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    /// The test `cluster_lights.comp` runs for every cluster and light
+    pub fn intersects_sphere(&self, center: &Vec3, radius: f32) -> bool {
+        let closest = glm::clamp_vec(center, &self.min, &self.max);
+        glm::distance2(&closest, center) <= radius * radius
+    }
+}
+
+#[allow(dead_code)]
+impl ClusterGrid {
+    /// The grid with the near and far plane of `projection`
+    pub fn with_depth_range(self, projection: &Mat4) -> ClusterGrid {
+        let (near, far) = camera::depth_range(projection);
+        ClusterGrid { near, far, ..self }
+    }
+
+    pub fn count(&self) -> usize {
+        (self.tiles_x * self.tiles_y * self.slices) as usize
+    }
+
+    /// x varies fastest, then y, then the slice, like `clusterIndex` in `pbr.frag`
+    pub fn index(&self, [x, y, slice]: [u32; 3]) -> usize {
+        (x + self.tiles_x * (y + self.tiles_y * slice)) as usize
+    }
+
+    /// View space distance where `slice` starts, `slice_depth(slices)` is `far`
+    pub fn slice_depth(&self, slice: u32) -> f32 {
+        self.near * (self.far / self.near).powf(slice as f32 / self.slices as f32)
+    }
+
+    /// The cluster of a fragment at `screen`, its framebuffer position divided by the size, and
+    /// view space distance `depth`, like `clusterIndex` in `pbr.frag`
+    pub fn cluster(&self, [x, y]: [f32; 2], depth: f32) -> [u32; 3] {
+        let tile = |coord: f32, tiles: u32| ((coord * tiles as f32) as u32).min(tiles - 1);
+        [
+            tile(x, self.tiles_x),
+            tile(y, self.tiles_y),
+            self.slice(depth),
+        ]
+    }
+
+    /// The slice a view space distance falls into, clamped to the grid like in the shader
+    pub fn slice(&self, depth: f32) -> u32 {
+        if depth <= self.near {
+            return 0;
+        }
+
+        let slice = (depth / self.near).ln() / (self.far / self.near).ln() * self.slices as f32;
+        (slice as u32).min(self.slices - 1)
+    }
+
+    /// Bounds of one cluster, from the corners of its tile on the near and far plane of its
+    /// slice
+    pub fn bounds(&self, inverse_projection: &Mat4, [x, y, slice]: [u32; 3]) -> Aabb {
+        let ndc = |tile: u32, tiles: u32| -1.0 + 2.0 * tile as f32 / tiles as f32;
+        let depths = [self.slice_depth(slice), self.slice_depth(slice + 1)];
+
+        let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = -min;
+
+        for ndc_x in [ndc(x, self.tiles_x), ndc(x + 1, self.tiles_x)] {
+            for ndc_y in [ndc(y, self.tiles_y), ndc(y + 1, self.tiles_y)] {
+                let near = inverse_projection * glm::vec4(ndc_x, ndc_y, -1.0, 1.0);
+                let ray = near.xyz() / near.w;
+
+                for depth in depths {
+                    // The camera looks down -z, so the ray reaches `depth` at `z = -depth`
+                    let corner = ray * (depth / -ray.z);
+                    min = glm::min2(&min, &corner);
+                    max = glm::max2(&max, &corner);
+                }
+            }
+        }
+
+        Aabb { min, max }
+    }
+
+    /// Bounds of every cluster, in `index` order, sliced between the clip planes of
+    /// `projection`
+    pub fn all_bounds(&self, projection: &Mat4) -> Vec<Aabb> {
+        let grid = self.with_depth_range(projection);
+        let inverse_projection = glm::inverse(projection);
+        let mut bounds = Vec::with_capacity(grid.count());
+
+        for slice in 0..grid.slices {
+            for y in 0..grid.tiles_y {
+                for x in 0..grid.tiles_x {
+                    bounds.push(grid.bounds(&inverse_projection, [x, y, slice]));
+                }
+            }
+        }
+
+        bounds
+    }
+}
+
+/// A point light for clustered shading. It falls off to exactly zero at `range`, so clusters
+/// further away can skip it
+#[derive(Clone, Copy, Debug)]
+pub struct ClusterLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub range: f32,
+}
+
+/// Mirrors `ClusterBlock` in `cluster_lights.comp` and `pbr.frag`
+#[repr(C)]
+struct ClusterBlockData {
+    view: [f32; 16],
+    counts: [u32; 4],
+    params: [f32; 4],
+}
+
+/// Bins the lights into the clusters of a `ClusterGrid` with a compute pass every frame. The
+/// storage buffers stay bound, so `pbr.frag` compiled with `CLUSTERED` can read the light lists
+pub struct ClusteredLights {
+    grid: ClusterGrid,
+    pipeline: ComputePipeline,
+    block: UBO,
+    capacity: usize,
+    lights: Buffer,
+    bounds: Buffer,
+    ranges: Buffer,
+    indices: Buffer,
+    /// The projection `bounds` was built for
+    projection: Option<Mat4>,
+}
+
+#[allow(dead_code)]
+impl ClusteredLights {
+    /// Room for `capacity` lights per frame
+    pub fn new(grid: ClusterGrid, capacity: usize) -> ClusteredLights {
+        let pipeline = ComputePipeline::new("shaders/cluster_lights.comp");
+        let block = UBO::with_binding(mem::size_of::<ClusterBlockData>(), CLUSTER_BLOCK_BINDING);
+        block.attach_new_shader(pipeline.shader(), "ClusterBlock");
+
+        let count = grid.count() as isize;
+
+        ClusteredLights {
+            grid,
+            pipeline,
+            block,
+            capacity,
+            lights: Buffer::create(
+                capacity.max(1) as isize * 2 * mem::size_of::<[f32; 4]>() as isize,
+            ),
+            bounds: Buffer::create(count * 2 * mem::size_of::<[f32; 4]>() as isize),
+            ranges: Buffer::create(count * mem::size_of::<[u32; 2]>() as isize),
+            indices: Buffer::create(
+                count * (MAX_LIGHTS_PER_CLUSTER * mem::size_of::<u32>()) as isize,
+            ),
+            projection: None,
+        }
+    }
+
+    pub fn grid(&self) -> &ClusterGrid {
+        &self.grid
+    }
+
+    pub fn attach_shader(&self, shader: &Shader) {
+        self.block.attach_new_shader(shader, "ClusterBlock");
+    }
+
+    /// Uploads the lights and rebuilds the per-cluster light lists. The cluster bounds and the
+    /// depth slices only depend on the projection, so they are rebuilt when it changes
+    pub fn update(
+        &mut self,
+        lights: &[ClusterLight],
+        view: &Mat4,
+        projection: &Mat4,
+        width: i32,
+        height: i32,
+    ) {
+        assert!(
+            lights.len() <= self.capacity,
+            "Too many clustered lights, the maximum is {}",
+            self.capacity
+        );
+
+        if self.projection != Some(*projection) {
+            self.grid = self.grid.with_depth_range(projection);
+            let bounds: Vec<[[f32; 4]; 2]> = self
+                .grid
+                .all_bounds(projection)
+                .iter()
+                .map(|bounds| [vec4(&bounds.min, 1.0), vec4(&bounds.max, 1.0)])
+                .collect();
+
+            self.bounds.write(0, &bounds);
+            self.projection = Some(*projection);
+        }
+
+        let data: Vec<[[f32; 4]; 2]> = lights
+            .iter()
+            .map(|light| [vec4(&light.position, light.range), vec4(&light.color, 1.0)])
+            .collect();
+        self.lights.write(0, &data);
+
+        let mut view_data = [0.0; 16];
+        view_data.copy_from_slice(view.as_slice());
+
+        let block = ClusterBlockData {
+            view: view_data,
+            counts: [
+                self.grid.tiles_x,
+                self.grid.tiles_y,
+                self.grid.slices,
+                lights.len() as u32,
+            ],
+            params: [self.grid.near, self.grid.far, width as f32, height as f32],
+        };
+        self.block.write(0, std::slice::from_ref(&block));
+
+        self.pipeline
+            .bind_storage_buffer(LIGHTS_BINDING, &self.lights)
+            .bind_storage_buffer(BOUNDS_BINDING, &self.bounds)
+            .bind_storage_buffer(RANGES_BINDING, &self.ranges)
+            .bind_storage_buffer(INDICES_BINDING, &self.indices)
+            .dispatch_invocations(self.grid.count() as u32, 1, 1);
+
+        self.pipeline.barrier(MemoryBarrier::SHADER_STORAGE);
+    }
+}
+
+fn vec4(vec: &Vec3, w: f32) -> [f32; 4] {
+    [vec.x, vec.y, vec.z, w]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift, the scenes only have to be reproducible
+    fn random(state: &mut u32, min: f32, max: f32) -> f32 {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        min + (max - min) * (*state as f32 / u32::MAX as f32)
+    }
+
+    /// A grid with the clip planes of the returned projection
+    fn random_grid(state: &mut u32) -> (ClusterGrid, Mat4, f32, f32) {
+        let near = random(state, 0.05, 1.0);
+        let far = random(state, 20.0, 200.0);
+        let aspect = random(state, 0.5, 2.5);
+        let fovy = random(state, 30.0_f32, 90.0).to_radians();
+        let projection = glm::perspective(aspect, fovy, near, far);
+
+        let grid = ClusterGrid {
+            tiles_x: random(state, 1.0, 24.0) as u32,
+            tiles_y: random(state, 1.0, 16.0) as u32,
+            slices: random(state, 1.0, 24.0) as u32,
+            ..ClusterGrid::default()
+        }
+        .with_depth_range(&projection);
+
+        (grid, projection, aspect, fovy)
+    }
+
+    /// A view space point inside the frustum and the screen position it projects to, built from
+    /// the field of view instead of the inverse projection `bounds` uses
+    fn random_point(
+        state: &mut u32,
+        grid: &ClusterGrid,
+        aspect: f32,
+        fovy: f32,
+    ) -> (Vec3, [f32; 2]) {
+        let screen = [random(state, 0.0, 1.0), random(state, 0.0, 1.0)];
+        let depth = random(state, grid.near, grid.far);
+        let half_height = depth * (fovy / 2.0).tan();
+
+        let point = glm::vec3(
+            (screen[0] * 2.0 - 1.0) * half_height * aspect,
+            (screen[1] * 2.0 - 1.0) * half_height,
+            -depth,
+        );
+        (point, screen)
+    }
+
+    #[test]
+    fn the_depth_range_comes_from_the_projection() {
+        let mut state = 0x68E3_1DA4_u32;
+
+        for _ in 0..20 {
+            let near = random(&mut state, 0.05, 1.0);
+            let far = random(&mut state, 20.0, 200.0);
+            let projection = glm::perspective(random(&mut state, 0.5, 2.5), 1.0, near, far);
+
+            let grid = ClusterGrid::default().with_depth_range(&projection);
+            assert!(
+                (grid.near - near).abs() <= near * 1e-4,
+                "{grid:?}, near {near}"
+            );
+            assert!((grid.far - far).abs() <= far * 1e-3, "{grid:?}, far {far}");
+        }
+    }
+
+    #[test]
+    fn slices_start_where_slice_depth_says() {
+        let mut state = 0x2545_f491_u32;
+
+        for _ in 0..20 {
+            let (grid, _, _, _) = random_grid(&mut state);
+
+            assert_eq!(grid.slice(grid.near * 0.5), 0);
+            assert_eq!(grid.slice(grid.far * 2.0), grid.slices - 1);
+
+            for slice in 0..grid.slices {
+                let middle = (grid.slice_depth(slice) + grid.slice_depth(slice + 1)) / 2.0;
+                assert_eq!(grid.slice(middle), slice, "{grid:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn every_point_lies_in_the_bounds_of_its_cluster() {
+        let mut state = 0x9E37_79B9_u32;
+
+        for scene in 0..20 {
+            let (grid, projection, aspect, fovy) = random_grid(&mut state);
+            let bounds = grid.all_bounds(&projection);
+
+            for _ in 0..500 {
+                let (point, screen) = random_point(&mut state, &grid, aspect, fovy);
+                let cluster = grid.cluster(screen, -point.z);
+                let Aabb { min, max } = bounds[grid.index(cluster)];
+
+                // Relative slack for the float error of the box corners
+                let slack = 1e-4 * point.norm().max(1.0);
+                let inside = (0..3).all(|axis| {
+                    min[axis] - slack <= point[axis] && point[axis] <= max[axis] + slack
+                });
+                assert!(
+                    inside,
+                    "scene {scene}: {point:?} at {screen:?} is outside cluster {cluster:?} \
+                     {min:?}..{max:?} of {grid:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lit_points_find_the_light_in_their_cluster() {
+        let mut state = 0x1B87_3593_u32;
+        let mut lit = 0;
+
+        for scene in 0..20 {
+            let (grid, projection, aspect, fovy) = random_grid(&mut state);
+            let bounds = grid.all_bounds(&projection);
+
+            let spheres: Vec<(Vec3, f32)> = (0..50)
+                .map(|_| {
+                    let (center, _) = random_point(&mut state, &grid, aspect, fovy);
+                    (center, random(&mut state, 0.01, 10.0))
+                })
+                .collect();
+
+            for _ in 0..500 {
+                let (point, screen) = random_point(&mut state, &grid, aspect, fovy);
+                let cluster = grid.index(grid.cluster(screen, -point.z));
+
+                // Every light reaching the point has to be in the list the shader reads for it
+                for (center, radius) in &spheres {
+                    if glm::distance(&point, center) < *radius * 0.999 {
+                        assert!(
+                            bounds[cluster].intersects_sphere(center, *radius),
+                            "scene {scene}: {point:?} is lit by {center:?} with radius {radius}, \
+                             but cluster {cluster} of {grid:?} misses it"
+                        );
+                        lit += 1;
+                    }
+                }
+            }
+        }
+
+        // The spheres have to reach some points to test anything
+        assert!(lit > 0);
+    }
+}
@@ -0,0 +1,246 @@
+use std::ops::{BitOr, BitOrAssign};
+
+use opengl::gl;
+
+use crate::{buffers::Buffer, shaders::Shader, textures::TextureBindings};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryBarrier(gl::types::GLbitfield);
+
+#[allow(dead_code)]
+impl MemoryBarrier {
+    pub const VERTEX_ATTRIB_ARRAY: MemoryBarrier =
+        MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    pub const ELEMENT_ARRAY: MemoryBarrier = MemoryBarrier(gl::ELEMENT_ARRAY_BARRIER_BIT);
+    pub const UNIFORM: MemoryBarrier = MemoryBarrier(gl::UNIFORM_BARRIER_BIT);
+    pub const TEXTURE_FETCH: MemoryBarrier = MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+    pub const SHADER_IMAGE_ACCESS: MemoryBarrier =
+        MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    pub const COMMAND: MemoryBarrier = MemoryBarrier(gl::COMMAND_BARRIER_BIT);
+    pub const PIXEL_BUFFER: MemoryBarrier = MemoryBarrier(gl::PIXEL_BUFFER_BARRIER_BIT);
+    pub const TEXTURE_UPDATE: MemoryBarrier = MemoryBarrier(gl::TEXTURE_UPDATE_BARRIER_BIT);
+    pub const BUFFER_UPDATE: MemoryBarrier = MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+    pub const FRAMEBUFFER: MemoryBarrier = MemoryBarrier(gl::FRAMEBUFFER_BARRIER_BIT);
+    pub const ATOMIC_COUNTER: MemoryBarrier = MemoryBarrier(gl::ATOMIC_COUNTER_BARRIER_BIT);
+    pub const SHADER_STORAGE: MemoryBarrier = MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+    pub const ALL: MemoryBarrier = MemoryBarrier(gl::ALL_BARRIER_BITS);
+
+    pub fn issue(self) {
+        unsafe {
+            gl::MemoryBarrier(self.0);
+        }
+    }
+}
+
+impl BitOr for MemoryBarrier {
+    type Output = MemoryBarrier;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        MemoryBarrier(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MemoryBarrier {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl ImageAccess {
+    fn as_gl(self) -> gl::types::GLenum {
+        match self {
+            ImageAccess::ReadOnly => gl::READ_ONLY,
+            ImageAccess::WriteOnly => gl::WRITE_ONLY,
+            ImageAccess::ReadWrite => gl::READ_WRITE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ComputeLimits {
+    pub work_group_count: [u32; 3],
+    pub work_group_size: [u32; 3],
+    pub work_group_invocations: u32,
+}
+
+impl ComputeLimits {
+    pub fn query() -> ComputeLimits {
+        let mut work_group_count = [0; 3];
+        let mut work_group_size = [0; 3];
+        let mut work_group_invocations = 0;
+
+        unsafe {
+            for axis in 0..3 {
+                let mut value = 0;
+                gl::GetIntegeri_v(gl::MAX_COMPUTE_WORK_GROUP_COUNT, axis, &mut value);
+                work_group_count[axis as usize] = value as u32;
+
+                gl::GetIntegeri_v(gl::MAX_COMPUTE_WORK_GROUP_SIZE, axis, &mut value);
+                work_group_size[axis as usize] = value as u32;
+            }
+
+            gl::GetIntegerv(
+                gl::MAX_COMPUTE_WORK_GROUP_INVOCATIONS,
+                &mut work_group_invocations,
+            );
+        }
+
+        ComputeLimits {
+            work_group_count,
+            work_group_size,
+            work_group_invocations: work_group_invocations as u32,
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    shader: Shader,
+    local_size: [u32; 3],
+    limits: ComputeLimits,
+}
+
+#[allow(dead_code)]
+impl ComputePipeline {
+    pub fn new(c_shader_file: &str) -> ComputePipeline {
+        let shader = Shader::new_compute(c_shader_file);
+        let limits = ComputeLimits::query();
+
+        let mut local_size = [0; 3];
+        unsafe {
+            gl::GetProgramiv(
+                shader.id,
+                gl::COMPUTE_WORK_GROUP_SIZE,
+                local_size.as_mut_ptr(),
+            );
+        }
+        let local_size = local_size.map(|size| size as u32);
+
+        for axis in 0..3 {
+            assert!(
+                local_size[axis] <= limits.work_group_size[axis],
+                "{c_shader_file}: local_size {local_size:?} exceeds MAX_COMPUTE_WORK_GROUP_SIZE {:?}",
+                limits.work_group_size
+            );
+        }
+
+        let invocations = local_size.iter().product::<u32>();
+        assert!(
+            invocations <= limits.work_group_invocations,
+            "{c_shader_file}: {invocations} invocations exceed MAX_COMPUTE_WORK_GROUP_INVOCATIONS {}",
+            limits.work_group_invocations
+        );
+
+        ComputePipeline {
+            shader,
+            local_size,
+            limits,
+        }
+    }
+
+    pub fn shader(&self) -> &Shader {
+        &self.shader
+    }
+
+    pub fn local_size(&self) -> [u32; 3] {
+        self.local_size
+    }
+
+    pub fn limits(&self) -> &ComputeLimits {
+        &self.limits
+    }
+
+    pub fn bind_storage_buffer(&self, index: u32, buffer: &Buffer) -> &Self {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, index, buffer.id());
+        }
+        self
+    }
+
+    pub fn bind_image(
+        &self,
+        unit: u32,
+        texture: u32,
+        level: i32,
+        access: ImageAccess,
+        format: gl::types::GLenum,
+    ) -> &Self {
+        unsafe {
+            gl::BindImageTexture(unit, texture, level, gl::FALSE, 0, access.as_gl(), format);
+        }
+        self
+    }
+
+    pub fn bind_image_layer(
+        &self,
+        unit: u32,
+        texture: u32,
+        level: i32,
+        layer: Option<i32>,
+        access: ImageAccess,
+        format: gl::types::GLenum,
+    ) -> &Self {
+        let (layered, layer) = match layer {
+            Some(layer) => (gl::FALSE, layer),
+            None => (gl::TRUE, 0),
+        };
+
+        unsafe {
+            gl::BindImageTexture(unit, texture, level, layered, layer, access.as_gl(), format);
+        }
+        self
+    }
+
+    /// Binds `textures` and their samplers to consecutive units from `first`
+    pub fn bind_textures(&self, textures: &TextureBindings, first: u32) -> &Self {
+        textures.bind(first);
+        self
+    }
+
+    pub fn dispatch(&self, x: u32, y: u32, z: u32) {
+        let groups = [x, y, z];
+        for axis in 0..3 {
+            assert!(
+                groups[axis] <= self.limits.work_group_count[axis],
+                "Dispatch {groups:?} exceeds MAX_COMPUTE_WORK_GROUP_COUNT {:?}",
+                self.limits.work_group_count
+            );
+        }
+
+        self.shader.use_program();
+        unsafe {
+            gl::DispatchCompute(x, y, z);
+        }
+    }
+
+    /// Dispatches enough work groups to cover `x * y * z` invocations.
+    pub fn dispatch_invocations(&self, x: u32, y: u32, z: u32) {
+        let [size_x, size_y, size_z] = self.local_size;
+        self.dispatch(x.div_ceil(size_x), y.div_ceil(size_y), z.div_ceil(size_z));
+    }
+
+    /// The group counts live on the GPU, so `MAX_COMPUTE_WORK_GROUP_COUNT` can't be checked here.
+    pub fn dispatch_indirect(&self, buffer: &Buffer, offset: isize) {
+        assert!(
+            offset % 4 == 0,
+            "Indirect dispatch offset must be 4 byte aligned"
+        );
+
+        self.shader.use_program();
+        unsafe {
+            gl::BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, buffer.id());
+            gl::DispatchComputeIndirect(offset);
+        }
+    }
+
+    pub fn barrier(&self, barriers: MemoryBarrier) {
+        barriers.issue();
+    }
+}
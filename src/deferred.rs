@@ -0,0 +1,244 @@
+use std::ffi::OsString;
+
+use nalgebra_glm::{self as glm, Mat4};
+use opengl::gl;
+
+use crate::fullscreen::FullscreenTriangle;
+use crate::ibl::Environment;
+use crate::material::Material;
+use crate::pbr;
+use crate::shaders::Shader;
+use crate::shadows::Shadows;
+use crate::textures::TextureHandle;
+
+pub const RENDERER_FLAG: &str = "--renderer";
+
+/// Format and sampler name in `pbr.frag` with `DEFERRED` of every color target, in attachment
+/// order. The albedo is linear after sampling the sRGB texture, sRGB storage keeps the dark tones
+/// from banding
+const GBUFFER_TARGETS: [(gl::types::GLenum, &str); 4] = [
+    (gl::SRGB8_ALPHA8, "gAlbedo"),
+    (gl::RGBA16F, "gNormal"),
+    (gl::RGBA8, "gMaterial"),
+    (gl::RGBA16F, "gEmissive"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+    /// Forward, with the point lights binned into clusters by `ClusteredLights`
+    Clustered,
+}
+
+impl RenderPath {
+    /// `--renderer forward|deferred|clustered` or `--renderer=...`, forward when not given
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> RenderPath {
+        let mut args = args.into_iter();
+        let mut value = None;
+
+        while let Some(arg) = args.next() {
+            if arg == RENDERER_FLAG {
+                value = args.next();
+                break;
+            }
+
+            if let Some(path) = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix(RENDERER_FLAG)?.strip_prefix('='))
+            {
+                value = Some(OsString::from(path));
+                break;
+            }
+        }
+
+        match value.as_ref().and_then(|value| value.to_str()) {
+            None | Some("forward") => RenderPath::Forward,
+            Some("deferred") => RenderPath::Deferred,
+            Some("clustered") => RenderPath::Clustered,
+            Some(other) => {
+                println!(
+                    "Unknown renderer `{other}`, expected `forward`, `deferred` or `clustered`"
+                );
+                RenderPath::Forward
+            }
+        }
+    }
+}
+
+/// The render targets of the geometry pass. The depth is `DEPTH24_STENCIL8` to match the default
+/// framebuffer, otherwise it can't be blitted there
+struct GBuffer {
+    framebuffer: u32,
+    targets: [TextureHandle; GBUFFER_TARGETS.len()],
+    depth: TextureHandle,
+    width: i32,
+    height: i32,
+}
+
+impl GBuffer {
+    fn new(width: i32, height: i32) -> GBuffer {
+        let mut framebuffer = 0;
+        let targets =
+            GBUFFER_TARGETS.map(|(format, name)| create_target(format, name, width, height));
+        let depth = create_target(gl::DEPTH24_STENCIL8, "gDepth", width, height);
+
+        unsafe {
+            gl::CreateFramebuffers(1, &mut framebuffer);
+
+            for (index, texture) in targets.iter().enumerate() {
+                gl::NamedFramebufferTexture(
+                    framebuffer,
+                    gl::COLOR_ATTACHMENT0 + index as u32,
+                    texture.id(),
+                    0,
+                );
+            }
+
+            gl::NamedFramebufferTexture(framebuffer, gl::DEPTH_STENCIL_ATTACHMENT, depth.id(), 0);
+
+            let attachments: Vec<_> = (0..targets.len() as u32)
+                .map(|index| gl::COLOR_ATTACHMENT0 + index)
+                .collect();
+            gl::NamedFramebufferDrawBuffers(
+                framebuffer,
+                attachments.len() as i32,
+                attachments.as_ptr(),
+            );
+
+            let status = gl::CheckNamedFramebufferStatus(framebuffer, gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                panic!("G-Buffer Error: incomplete, status {status:#x}");
+            }
+        }
+
+        GBuffer {
+            framebuffer,
+            targets,
+            depth,
+            width,
+            height,
+        }
+    }
+}
+
+impl Drop for GBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+        }
+    }
+}
+
+/// Opaque geometry goes into the G-buffer with `gbuffer.frag` materials, then one fullscreen
+/// pass shades every pixel with all lights. Transparent objects are drawn forward afterwards
+pub struct DeferredRenderer<'a> {
+    gbuffer: GBuffer,
+    lighting: Material<'a>,
+    triangle: FullscreenTriangle,
+}
+
+#[allow(dead_code)]
+impl<'a> DeferredRenderer<'a> {
+    /// `lighting_shader` is `fullscreen.vert` with `pbr.frag` compiled with `DEFERRED`, attached
+    /// to the `LightBlock` and the `ShadowBlock` of `shadows`
+    pub fn new(
+        lighting_shader: &'a Shader,
+        environment: &Environment,
+        shadows: &Shadows,
+        width: i32,
+        height: i32,
+    ) -> DeferredRenderer<'a> {
+        let mut renderer = DeferredRenderer {
+            gbuffer: GBuffer::new(width, height),
+            lighting: pbr::with_environment(Material::new(lighting_shader), environment)
+                .with_texture("directionalShadow", shadows.directional_texture())
+                .with_texture("pointShadow", shadows.point_texture()),
+            triangle: FullscreenTriangle::new(),
+        };
+        renderer.set_gbuffer_textures();
+
+        renderer
+    }
+
+    /// Binds and clears the G-buffer, recreating it first when the window size changed. sRGB
+    /// writes are enabled until `light`, so the albedo is encoded on the way in
+    pub fn begin_geometry(&mut self, width: i32, height: i32) {
+        let resized = (width, height) != (self.gbuffer.width, self.gbuffer.height);
+
+        // Minimized windows report a zero size
+        if resized && width > 0 && height > 0 {
+            self.gbuffer = GBuffer::new(width, height);
+            self.set_gbuffer_textures();
+        }
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.gbuffer.framebuffer);
+            gl::Enable(gl::FRAMEBUFFER_SRGB);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// Clears the default framebuffer and shades the G-buffer into it, pixels where nothing was
+    /// drawn keep the clear color. Copies the depth over afterwards so forward passes are hidden
+    /// by the opaque geometry
+    pub fn light(&mut self, view: &Mat4, projection: &Mat4) {
+        self.lighting
+            .set("inverseViewProjection", glm::inverse(&(projection * view)))
+            .set("view", *view);
+
+        let (width, height) = (self.gbuffer.width, self.gbuffer.height);
+
+        unsafe {
+            // The lighting pass applies its own gamma
+            gl::Disable(gl::FRAMEBUFFER_SRGB);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            gl::Disable(gl::DEPTH_TEST);
+
+            self.lighting.apply();
+            self.triangle.draw();
+
+            gl::Enable(gl::DEPTH_TEST);
+            gl::BlitNamedFramebuffer(
+                self.gbuffer.framebuffer,
+                0,
+                0,
+                0,
+                width,
+                height,
+                0,
+                0,
+                width,
+                height,
+                gl::DEPTH_BUFFER_BIT,
+                gl::NEAREST,
+            );
+        }
+    }
+
+    fn set_gbuffer_textures(&mut self) {
+        for (texture, (_, name)) in self.gbuffer.targets.iter().zip(GBUFFER_TARGETS) {
+            self.lighting.set_texture(name, texture);
+        }
+
+        self.lighting.set_texture("gDepth", &self.gbuffer.depth);
+    }
+}
+
+fn create_target(format: gl::types::GLenum, name: &str, width: i32, height: i32) -> TextureHandle {
+    let mut texture = 0;
+
+    unsafe {
+        gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
+        gl::TextureStorage2D(texture, 1, format, width, height);
+
+        // Every pixel is read back exactly once, at its own position
+        gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    }
+
+    TextureHandle::new(texture, name)
+}
@@ -0,0 +1,40 @@
+use opengl::gl;
+
+/// The attributeless triangle `fullscreen.vert` and `skybox.vert` expand to cover the screen
+pub struct FullscreenTriangle {
+    vao: u32,
+}
+
+impl FullscreenTriangle {
+    pub fn new() -> FullscreenTriangle {
+        let mut vao = 0;
+        unsafe {
+            // The triangle has no attributes, but core profile still needs a VAO
+            gl::CreateVertexArrays(1, &mut vao);
+        }
+
+        FullscreenTriangle { vao }
+    }
+
+    /// Draws with the current program, keeping the bound vertex array
+    pub fn draw(&self) {
+        let mut vao = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::VERTEX_ARRAY_BINDING, &mut vao);
+
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            gl::BindVertexArray(vao as u32);
+        }
+    }
+}
+
+impl Drop for FullscreenTriangle {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -0,0 +1,658 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::Rgb32FImage;
+use opengl::gl;
+
+use crate::assets;
+use crate::fullscreen::FullscreenTriangle;
+use crate::shaders::{self, flags, Defines, Fnv1a, Shader};
+use crate::textures::TextureHandle;
+
+const IBL_VERTEX_SHADER: &str = "shaders/fullscreen.vert";
+const IBL_SHADER: &str = "shaders/ibl.frag";
+
+/// Bumped whenever the layout of a cache entry changes
+const CACHE_VERSION: u32 = 1;
+const CACHE_MAGIC: &[u8; 4] = b"IBL\0";
+
+#[derive(Clone, Copy, Debug)]
+pub struct IblSettings {
+    /// Face size of the environment cube map converted from the equirectangular image
+    pub environment_size: i32,
+    pub irradiance_size: i32,
+    /// Face size of the sharpest prefiltered level, every level halves it
+    pub prefilter_size: i32,
+    /// Roughness 0.0 maps to the first level and 1.0 to the last. Clamped to the levels
+    /// `prefilter_size` has, and to at least one
+    pub prefilter_levels: i32,
+    pub brdf_size: i32,
+}
+
+impl IblSettings {
+    fn clamped(self) -> IblSettings {
+        IblSettings {
+            prefilter_levels: self
+                .prefilter_levels
+                .clamp(1, mip_levels(self.prefilter_size)),
+            ..self
+        }
+    }
+}
+
+impl Default for IblSettings {
+    fn default() -> Self {
+        IblSettings {
+            environment_size: 512,
+            irradiance_size: 32,
+            prefilter_size: 128,
+            prefilter_levels: 5,
+            brdf_size: 512,
+        }
+    }
+}
+
+/// Image based lighting precomputed from an equirectangular HDR image: the environment itself,
+/// its diffuse irradiance, the specular reflections prefiltered per roughness and the BRDF
+/// lookup table of the split sum approximation
+pub struct Environment {
+    environment: TextureHandle,
+    irradiance: TextureHandle,
+    prefiltered: TextureHandle,
+    brdf_lut: TextureHandle,
+    settings: IblSettings,
+}
+
+#[allow(dead_code)]
+impl Environment {
+    /// Renders every map at load time, unless `cache` has them for this image and settings
+    pub fn from_equirect(
+        path: &str,
+        settings: IblSettings,
+        cache: Option<&IblCache>,
+    ) -> Environment {
+        let settings = settings.clamped();
+        let bytes = assets::read(path);
+
+        let Ok(bytes) = bytes else {
+            panic!("Environment Error: {bytes:?}")
+        };
+
+        let environment = Environment::allocate(settings);
+        let key = cache_key(&bytes, &settings);
+
+        if cache.is_some_and(|cache| cache.load(key, &environment)) {
+            return environment;
+        }
+
+        let image = assets::decode(path, &bytes);
+
+        let Ok(image) = image else {
+            panic!("Environment Error: {path}: {image:?}")
+        };
+
+        environment.generate(&image.into_rgb32f());
+
+        if let Some(cache) = cache {
+            cache.store(key, &environment);
+        }
+
+        environment
+    }
+
+    /// The unfiltered environment as a mipmapped cube map, for a skybox
+    pub fn environment(&self) -> &TextureHandle {
+        &self.environment
+    }
+
+    pub fn irradiance(&self) -> &TextureHandle {
+        &self.irradiance
+    }
+
+    pub fn prefiltered(&self) -> &TextureHandle {
+        &self.prefiltered
+    }
+
+    /// Two channels, the scale and bias to F0 indexed by `NdotV` and roughness
+    pub fn brdf_lut(&self) -> &TextureHandle {
+        &self.brdf_lut
+    }
+
+    pub fn prefilter_levels(&self) -> i32 {
+        self.settings.prefilter_levels
+    }
+
+    fn allocate(settings: IblSettings) -> Environment {
+        let environment_levels = mip_levels(settings.environment_size);
+
+        Environment {
+            environment: TextureHandle::new(
+                create_texture(
+                    gl::TEXTURE_CUBE_MAP,
+                    environment_levels,
+                    gl::RGBA16F,
+                    settings.environment_size,
+                ),
+                "environment",
+            ),
+            irradiance: TextureHandle::new(
+                create_texture(
+                    gl::TEXTURE_CUBE_MAP,
+                    1,
+                    gl::RGBA16F,
+                    settings.irradiance_size,
+                ),
+                "irradiance",
+            ),
+            prefiltered: TextureHandle::new(
+                create_texture(
+                    gl::TEXTURE_CUBE_MAP,
+                    settings.prefilter_levels,
+                    gl::RGBA16F,
+                    settings.prefilter_size,
+                ),
+                "prefiltered",
+            ),
+            brdf_lut: TextureHandle::new(
+                create_texture(gl::TEXTURE_2D, 1, gl::RG16F, settings.brdf_size),
+                "BRDF LUT",
+            ),
+            settings,
+        }
+    }
+
+    fn generate(&self, image: &Rgb32FImage) {
+        let settings = &self.settings;
+        let mut equirect = 0;
+
+        unsafe {
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut equirect);
+            gl::TextureStorage2D(
+                equirect,
+                1,
+                gl::RGB32F,
+                image.width() as i32,
+                image.height() as i32,
+            );
+            gl::TextureSubImage2D(
+                equirect,
+                0,
+                0,
+                0,
+                image.width() as i32,
+                image.height() as i32,
+                gl::RGB,
+                gl::FLOAT,
+                image.as_raw().as_ptr() as *const _,
+            );
+            gl::TextureParameteri(equirect, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TextureParameteri(equirect, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TextureParameteri(equirect, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TextureParameteri(equirect, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        }
+
+        render_passes(|framebuffer, triangle| {
+            let equirect_to_cube = ibl_pass(Defines::new().flag(flags::EQUIRECT_TO_CUBE));
+            unsafe {
+                gl::BindTextureUnit(0, equirect);
+            }
+            render_cube(
+                triangle,
+                framebuffer,
+                self.environment.id(),
+                0,
+                settings.environment_size,
+                &equirect_to_cube,
+            );
+
+            unsafe {
+                gl::GenerateTextureMipmap(self.environment.id());
+                gl::BindTextureUnit(0, self.environment.id());
+            }
+
+            let irradiance = ibl_pass(Defines::new().flag(flags::IRRADIANCE));
+            render_cube(
+                triangle,
+                framebuffer,
+                self.irradiance.id(),
+                0,
+                settings.irradiance_size,
+                &irradiance,
+            );
+
+            let prefilter = ibl_pass(Defines::new().flag(flags::PREFILTER));
+            prefilter.set_uniform("environmentSize", settings.environment_size as f32);
+
+            for level in 0..settings.prefilter_levels {
+                let roughness = level as f32 / (settings.prefilter_levels - 1).max(1) as f32;
+                prefilter.set_uniform("roughness", roughness);
+
+                render_cube(
+                    triangle,
+                    framebuffer,
+                    self.prefiltered.id(),
+                    level,
+                    settings.prefilter_size >> level,
+                    &prefilter,
+                );
+            }
+
+            let brdf = ibl_pass(Defines::new());
+            brdf.use_program();
+
+            unsafe {
+                gl::NamedFramebufferTexture(
+                    framebuffer,
+                    gl::COLOR_ATTACHMENT0,
+                    self.brdf_lut.id(),
+                    0,
+                );
+                check_framebuffer(framebuffer);
+
+                gl::Viewport(0, 0, settings.brdf_size, settings.brdf_size);
+                triangle.draw();
+
+                for shader in [equirect_to_cube, irradiance, prefilter, brdf] {
+                    gl::DeleteProgram(shader.id);
+                }
+            }
+        });
+
+        unsafe {
+            gl::DeleteTextures(1, &equirect);
+        }
+    }
+
+    /// Every texture level stored in the cache, the environment mipmaps are regenerated instead
+    fn sections(&self) -> Vec<Section> {
+        let settings = &self.settings;
+
+        let mut sections = vec![
+            Section::cube(self.environment.id(), 0, settings.environment_size),
+            Section::cube(self.irradiance.id(), 0, settings.irradiance_size),
+        ];
+
+        sections.extend((0..settings.prefilter_levels).map(|level| {
+            Section::cube(
+                self.prefiltered.id(),
+                level,
+                settings.prefilter_size >> level,
+            )
+        }));
+
+        sections.push(Section {
+            texture: self.brdf_lut.id(),
+            level: 0,
+            size: settings.brdf_size,
+            format: gl::RG,
+            faces: 1,
+        });
+
+        sections
+    }
+}
+
+/// One texture level as stored in a cache entry, as half floats
+#[derive(Clone, Copy, Debug)]
+struct Section {
+    texture: u32,
+    level: i32,
+    size: i32,
+    format: gl::types::GLenum,
+    faces: i32,
+}
+
+impl Section {
+    fn cube(texture: u32, level: i32, size: i32) -> Section {
+        Section {
+            texture,
+            level,
+            size,
+            format: gl::RGBA,
+            faces: 6,
+        }
+    }
+
+    fn byte_len(&self) -> usize {
+        let components = if self.format == gl::RGBA { 4 } else { 2 };
+        (self.size * self.size * self.faces) as usize * components * 2
+    }
+
+    fn read(&self) -> Vec<u8> {
+        let mut data = vec![0u8; self.byte_len()];
+
+        unsafe {
+            gl::GetTextureImage(
+                self.texture,
+                self.level,
+                self.format,
+                gl::HALF_FLOAT,
+                data.len() as i32,
+                data.as_mut_ptr() as *mut _,
+            );
+        }
+
+        data
+    }
+
+    fn write(&self, data: &[u8]) {
+        unsafe {
+            if self.faces == 6 {
+                gl::TextureSubImage3D(
+                    self.texture,
+                    self.level,
+                    0,
+                    0,
+                    0,
+                    self.size,
+                    self.size,
+                    6,
+                    self.format,
+                    gl::HALF_FLOAT,
+                    data.as_ptr() as *const _,
+                );
+            } else {
+                gl::TextureSubImage2D(
+                    self.texture,
+                    self.level,
+                    0,
+                    0,
+                    self.size,
+                    self.size,
+                    self.format,
+                    gl::HALF_FLOAT,
+                    data.as_ptr() as *const _,
+                );
+            }
+        }
+    }
+}
+
+/// Precomputed environments stored as `<key>.ibl`, the magic and a little endian `u32` version
+/// followed by every texture level as half floats. Evicts the least recently used entries past
+/// `max_size`, like `ProgramBinaryCache`
+pub struct IblCache {
+    dir: PathBuf,
+    max_size: u64,
+    enabled: bool,
+}
+
+#[allow(dead_code)]
+impl IblCache {
+    pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> IblCache {
+        let dir = dir.into();
+
+        let enabled = match fs::create_dir_all(&dir) {
+            Ok(()) => true,
+            Err(error) => {
+                println!("Environment cache {dir:?} is not usable: {error}");
+                false
+            }
+        };
+
+        IblCache {
+            dir,
+            max_size,
+            enabled,
+        }
+    }
+
+    /// Uses `$IBL_CACHE_DIR` or the system temp directory, capped at 64 MiB
+    pub fn from_env() -> IblCache {
+        let dir = std::env::var_os("IBL_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::temp_dir().join("opengl-stuffs-uwu").join("ibl"));
+
+        IblCache::new(dir, shaders::DEFAULT_MAX_SIZE)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        for (path, _, _) in shaders::entries(&self.dir, "ibl")? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Fills `environment` from the entry, returns `false` when there is none or it's damaged
+    fn load(&self, key: u64, environment: &Environment) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let path = self.path(key);
+        let Ok(data) = fs::read(&path) else {
+            return false;
+        };
+
+        let sections = environment.sections();
+        let expected = 8 + sections.iter().map(Section::byte_len).sum::<usize>();
+
+        if data.len() != expected
+            || &data[..4] != CACHE_MAGIC
+            || data[4..8] != CACHE_VERSION.to_le_bytes()
+        {
+            let _ = fs::remove_file(&path);
+            return false;
+        }
+
+        let mut offset = 8;
+        for section in &sections {
+            let len = section.byte_len();
+            section.write(&data[offset..offset + len]);
+            offset += len;
+        }
+
+        unsafe {
+            gl::GenerateTextureMipmap(environment.environment.id());
+        }
+
+        shaders::touch(&path);
+
+        true
+    }
+
+    fn store(&self, key: u64, environment: &Environment) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(CACHE_MAGIC);
+        data.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+
+        for section in environment.sections() {
+            data.extend_from_slice(&section.read());
+        }
+
+        let path = self.path(key);
+        let result = shaders::write_entry(&path, &data)
+            .and_then(|()| shaders::evict(&self.dir, "ibl", self.max_size));
+
+        if let Err(error) = result {
+            println!("Failed to write environment cache {path:?}: {error}");
+        }
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.ibl"))
+    }
+}
+
+/// Covers the image, the settings and the shaders, so editing any of them regenerates the maps
+fn cache_key(image: &[u8], settings: &IblSettings) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write(&CACHE_VERSION.to_le_bytes());
+    hash.write(&(image.len() as u64).to_le_bytes());
+    hash.write(image);
+
+    for value in [
+        settings.environment_size,
+        settings.irradiance_size,
+        settings.prefilter_size,
+        settings.prefilter_levels,
+        settings.brdf_size,
+    ] {
+        hash.write(&value.to_le_bytes());
+    }
+
+    for shader in [IBL_VERTEX_SHADER, IBL_SHADER] {
+        if let Ok(source) = assets::read(shader) {
+            hash.write(&(source.len() as u64).to_le_bytes());
+            hash.write(&source);
+        }
+    }
+
+    hash.finish()
+}
+
+/// Renders the equirectangular panorama `equirect` into level 0 of every face of `cube`, which
+/// needs a color renderable format
+pub(crate) fn equirect_to_cube(equirect: u32, cube: u32, size: i32) {
+    render_passes(|framebuffer, triangle| {
+        let pass = ibl_pass(Defines::new().flag(flags::EQUIRECT_TO_CUBE));
+
+        unsafe {
+            gl::BindTextureUnit(0, equirect);
+        }
+        render_cube(triangle, framebuffer, cube, 0, size, &pass);
+
+        unsafe {
+            gl::DeleteProgram(pass.id);
+        }
+    });
+}
+
+/// Runs `passes` with a framebuffer of their own bound and the depth test off, then restores
+/// the viewport and depth test
+fn render_passes(passes: impl FnOnce(u32, &FullscreenTriangle)) {
+    let mut viewport = [0; 4];
+    let depth_test;
+
+    let mut framebuffer = 0;
+    let triangle = FullscreenTriangle::new();
+
+    unsafe {
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        depth_test = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+
+        gl::Disable(gl::DEPTH_TEST);
+        // Filtering across face edges, without it the blurry levels show seams
+        gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+
+        gl::CreateFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    }
+
+    passes(framebuffer, &triangle);
+
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::DeleteFramebuffers(1, &framebuffer);
+
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        if depth_test {
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+}
+
+fn ibl_pass(defines: Defines) -> Shader {
+    Shader::with_defines(IBL_VERTEX_SHADER, IBL_SHADER, &defines)
+}
+
+/// Draws the fullscreen triangle into every face of `level`
+fn render_cube(
+    triangle: &FullscreenTriangle,
+    framebuffer: u32,
+    texture: u32,
+    level: i32,
+    size: i32,
+    shader: &Shader,
+) {
+    shader.use_program();
+
+    unsafe {
+        gl::Viewport(0, 0, size, size);
+
+        for face in 0..6 {
+            gl::NamedFramebufferTextureLayer(
+                framebuffer,
+                gl::COLOR_ATTACHMENT0,
+                texture,
+                level,
+                face,
+            );
+            check_framebuffer(framebuffer);
+
+            shader.set_uniform("face", face);
+            triangle.draw();
+        }
+    }
+}
+
+fn check_framebuffer(framebuffer: u32) {
+    let status = unsafe { gl::CheckNamedFramebufferStatus(framebuffer, gl::FRAMEBUFFER) };
+    if status != gl::FRAMEBUFFER_COMPLETE {
+        panic!("Environment Framebuffer Error: incomplete, status {status:#x}");
+    }
+}
+
+fn create_texture(
+    target: gl::types::GLenum,
+    levels: i32,
+    format: gl::types::GLenum,
+    size: i32,
+) -> u32 {
+    let mut texture = 0;
+
+    let min_filter = if levels > 1 {
+        gl::LINEAR_MIPMAP_LINEAR
+    } else {
+        gl::LINEAR
+    };
+
+    unsafe {
+        gl::CreateTextures(target, 1, &mut texture);
+        gl::TextureStorage2D(texture, levels, format, size, size);
+
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    }
+
+    texture
+}
+
+fn mip_levels(size: i32) -> i32 {
+    32 - (size.max(1) as u32).leading_zeros() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefilter_levels_are_clamped_to_the_mip_chain() {
+        let levels = |prefilter_levels| {
+            IblSettings {
+                prefilter_size: 128,
+                prefilter_levels,
+                ..IblSettings::default()
+            }
+            .clamped()
+            .prefilter_levels
+        };
+
+        assert_eq!(levels(5), 5);
+        assert_eq!(levels(0), 1);
+        assert_eq!(levels(-3), 1);
+        assert_eq!(levels(20), 8);
+    }
+}
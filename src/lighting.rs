@@ -0,0 +1,172 @@
+use std::mem;
+
+use nalgebra_glm::{self as glm, Vec3};
+
+use crate::buffers::UBO;
+use crate::shaders::{Defines, Shader};
+
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+pub const MAX_POINT_LIGHTS: usize = 16;
+pub const MAX_SPOT_LIGHTS: usize = 8;
+
+/// Binding point of `LightBlock`, `MatrixBlock` uses 0
+pub const LIGHT_BLOCK_BINDING: u32 = 1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+#[allow(dead_code)]
+impl Attenuation {
+    /// Falls off to roughly 1% at `range`
+    pub fn range(range: f32) -> Attenuation {
+        Attenuation {
+            constant: 1.0,
+            linear: 4.5 / range,
+            quadratic: 75.0 / (range * range),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub attenuation: Attenuation,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub attenuation: Attenuation,
+    /// Full intensity inside this angle, in degrees
+    pub inner_cutoff: f32,
+    /// No light outside this angle, in degrees
+    pub outer_cutoff: f32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Lights {
+    pub ambient: Vec3,
+    pub directional: Vec<DirectionalLight>,
+    pub point: Vec<PointLight>,
+    pub spot: Vec<SpotLight>,
+}
+
+/// Mirrors `LightBlock` in `fragment.frag`, every member is a `vec4` so std140 adds no padding
+#[repr(C)]
+struct LightBlockData {
+    directional: [[[f32; 4]; 2]; MAX_DIRECTIONAL_LIGHTS],
+    point: [[[f32; 4]; 3]; MAX_POINT_LIGHTS],
+    spot: [[[f32; 4]; 5]; MAX_SPOT_LIGHTS],
+    counts: [u32; 4],
+    ambient: [f32; 4],
+    view_position: [f32; 4],
+}
+
+fn vec4(vec: &Vec3, w: f32) -> [f32; 4] {
+    [vec.x, vec.y, vec.z, w]
+}
+
+fn attenuation(attenuation: &Attenuation) -> [f32; 4] {
+    [
+        attenuation.constant,
+        attenuation.linear,
+        attenuation.quadratic,
+        0.0,
+    ]
+}
+
+/// The uniform buffer backing `LightBlock`
+pub struct LightBuffer {
+    ubo: UBO,
+}
+
+#[allow(dead_code)]
+impl LightBuffer {
+    pub fn new() -> LightBuffer {
+        LightBuffer {
+            ubo: UBO::with_binding(mem::size_of::<LightBlockData>(), LIGHT_BLOCK_BINDING),
+        }
+    }
+
+    /// The array sizes the shaders have to be compiled with to match `LightBlockData`
+    pub fn defines() -> Defines {
+        Defines::new()
+            .define("MAX_DIRECTIONAL_LIGHTS", MAX_DIRECTIONAL_LIGHTS)
+            .define("MAX_POINT_LIGHTS", MAX_POINT_LIGHTS)
+            .define("MAX_SPOT_LIGHTS", MAX_SPOT_LIGHTS)
+    }
+
+    pub fn attach_shader(&self, shader: &Shader) {
+        self.ubo.attach_new_shader(shader, "LightBlock");
+    }
+
+    /// Panics if there are more lights of a kind than the shaders have room for
+    pub fn upload(&self, lights: &Lights, view_position: &Vec3) {
+        assert!(
+            lights.directional.len() <= MAX_DIRECTIONAL_LIGHTS
+                && lights.point.len() <= MAX_POINT_LIGHTS
+                && lights.spot.len() <= MAX_SPOT_LIGHTS,
+            "Too many lights, the maximum is {MAX_DIRECTIONAL_LIGHTS} directional, \
+             {MAX_POINT_LIGHTS} point and {MAX_SPOT_LIGHTS} spot lights"
+        );
+
+        let mut data = LightBlockData {
+            directional: [[[0.0; 4]; 2]; MAX_DIRECTIONAL_LIGHTS],
+            point: [[[0.0; 4]; 3]; MAX_POINT_LIGHTS],
+            spot: [[[0.0; 4]; 5]; MAX_SPOT_LIGHTS],
+            counts: [
+                lights.directional.len() as u32,
+                lights.point.len() as u32,
+                lights.spot.len() as u32,
+                0,
+            ],
+            ambient: vec4(&lights.ambient, 1.0),
+            view_position: vec4(view_position, 1.0),
+        };
+
+        for (slot, light) in data.directional.iter_mut().zip(&lights.directional) {
+            *slot = [
+                vec4(&glm::normalize(&light.direction), 0.0),
+                vec4(&light.color, 1.0),
+            ];
+        }
+
+        for (slot, light) in data.point.iter_mut().zip(&lights.point) {
+            *slot = [
+                vec4(&light.position, 1.0),
+                vec4(&light.color, 1.0),
+                attenuation(&light.attenuation),
+            ];
+        }
+
+        for (slot, light) in data.spot.iter_mut().zip(&lights.spot) {
+            *slot = [
+                vec4(&light.position, 1.0),
+                vec4(&glm::normalize(&light.direction), 0.0),
+                vec4(&light.color, 1.0),
+                attenuation(&light.attenuation),
+                [
+                    light.inner_cutoff.to_radians().cos(),
+                    light.outer_cutoff.to_radians().cos(),
+                    0.0,
+                    0.0,
+                ],
+            ];
+        }
+
+        self.ubo.write(0, std::slice::from_ref(&data));
+    }
+}
@@ -1,11 +1,24 @@
+mod assets;
 mod buffers;
 mod camera;
+mod clusters;
+mod compute;
+mod deferred;
+mod fullscreen;
+mod ibl;
+mod lighting;
+mod material;
+mod pbr;
+mod samplers;
+#[cfg(feature = "check-shaders")]
+mod shader_check;
 mod shaders;
+mod shadows;
+mod skybox;
 mod textures;
 
 use std::collections::HashSet;
 use std::f32::consts::PI;
-use std::path::Path;
 use std::sync::mpsc::Receiver;
 use std::{mem, ptr};
 
@@ -14,13 +27,23 @@ use glm::{Mat4, Vec3};
 use nalgebra_glm as glm;
 use opengl::gl;
 
+use assets::Assets;
 use buffers::{Buffer, VertexBuilder, UBO};
 use camera::Camera;
-use shaders::Shader;
-use textures::{TextureBuilder, TextureManager};
-
-const VERTEX_SHADER_SOURCE: &str = "./resources/shaders/vertex.vert";
-const FRAGMENT_SHADER_SOURCE: &str = "./resources/shaders/fragment.frag";
+use clusters::{ClusterGrid, ClusterLight, ClusteredLights};
+use deferred::{DeferredRenderer, RenderPath};
+use ibl::{Environment, IblCache, IblSettings};
+use lighting::{Attenuation, DirectionalLight, LightBuffer, Lights, PointLight, SpotLight};
+use material::Material;
+use pbr::PbrTextures;
+use samplers::{SamplerCache, SamplerDesc};
+use shaders::{flags, Defines, ProgramBinaryCache, ShaderCache};
+use shadows::{ShadowSettings, Shadows};
+use skybox::Skybox;
+use textures::{TextureBuilder, TextureManager, TextureOptions};
+
+const VERTEX_SHADER_SOURCE: &str = "shaders/vertex.vert";
+const FRAGMENT_SHADER_SOURCE: &str = "shaders/fragment.frag";
 
 #[allow(dead_code)]
 const RADIANS: f32 = PI / 180.0;
@@ -30,6 +53,17 @@ fn gl_enable(cap: gl::types::GLenum) {
 }
 
 fn main() {
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    assets::init(Assets::from_args(args.iter().cloned()));
+    let render_path = RenderPath::from_args(args.iter().cloned());
+
+    if args
+        .first()
+        .is_some_and(|command| command == "check-shaders")
+    {
+        std::process::exit(check_shaders(&args[1..]));
+    }
+
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
 
     glfw.window_hint(glfw::WindowHint::ContextVersionMajor(4));
@@ -57,25 +91,107 @@ fn main() {
     // Learn OGL: https://learnopengl.com/
     // Learn OGL RS: https://github.com/bwasty/learn-opengl-rs
     // ECS: https://www.youtube.com/watch?v=aKLntZcp27M
-    let shaders = Shader::new(VERTEX_SHADER_SOURCE, FRAGMENT_SHADER_SOURCE);
-    let light_shader = Shader::new(
-        "./resources/shaders/light_vx.vert",
-        "./resources/shaders/light_fx.frag",
+    // Every variant compiled with a flag has to be listed in `shaders::PERMUTATIONS`, which
+    // `check-shaders` validates
+    let mut shader_cache = ShaderCache::with_binary_cache(ProgramBinaryCache::from_env());
+    // The light and shadow array sizes of every shader reading `LightBlock` and `ShadowBlock`
+    let lit_defines = LightBuffer::defines().merge(Shadows::defines());
+    let shader_handle =
+        shader_cache.get(VERTEX_SHADER_SOURCE, FRAGMENT_SHADER_SOURCE, &lit_defines);
+    let pbr_handle = shader_cache.get(VERTEX_SHADER_SOURCE, "shaders/pbr.frag", &lit_defines);
+    let clustered_pbr_handle = shader_cache.get(
+        VERTEX_SHADER_SOURCE,
+        "shaders/pbr.frag",
+        &lit_defines.clone().flag(flags::CLUSTERED),
+    );
+    let gbuffer_handle = shader_cache.get(
+        VERTEX_SHADER_SOURCE,
+        "shaders/gbuffer.frag",
+        &Defines::new(),
+    );
+    let deferred_lighting_handle = shader_cache.get(
+        "shaders/fullscreen.vert",
+        "shaders/pbr.frag",
+        &lit_defines.clone().flag(flags::DEFERRED),
+    );
+    let light_handle = shader_cache.get(
+        "shaders/light_vx.vert",
+        "shaders/light_fx.frag",
+        &Defines::new(),
+    );
+
+    let tile_handle = shader_cache.get(VERTEX_SHADER_SOURCE, "shaders/tile.frag", &Defines::new());
+    let gbuffer_tile_handle = shader_cache.get(
+        VERTEX_SHADER_SOURCE,
+        "shaders/tile.frag",
+        &Defines::new().flag(flags::DEFERRED),
+    );
+    let skybox_handle = shader_cache.get(
+        "shaders/skybox.vert",
+        "shaders/skybox.frag",
+        &Defines::new(),
+    );
+
+    let shadow_handle = shader_cache.get(
+        "shaders/shadow_depth.vert",
+        "shaders/shadow_depth.frag",
+        &Defines::new(),
+    );
+    let point_shadow_handle = shader_cache.get(
+        "shaders/shadow_depth.vert",
+        "shaders/shadow_depth.frag",
+        &Defines::new().flag(flags::POINT_SHADOW),
     );
 
+    let shaders = &shader_cache[shader_handle];
+    let pbr_shader = &shader_cache[pbr_handle];
+    let clustered_pbr_shader = &shader_cache[clustered_pbr_handle];
+    let gbuffer_shader = &shader_cache[gbuffer_handle];
+    let deferred_lighting_shader = &shader_cache[deferred_lighting_handle];
+    let light_shader = &shader_cache[light_handle];
+    let tile_shader = &shader_cache[tile_handle];
+    let gbuffer_tile_shader = &shader_cache[gbuffer_tile_handle];
+    let skybox_shader = &shader_cache[skybox_handle];
+    let shadow_shader = &shader_cache[shadow_handle];
+    let point_shadow_shader = &shader_cache[point_shadow_handle];
+
     #[rustfmt::skip]
-    let vertices: [f32; 20] = [
-        // Positions      | Texture coords
-         0.25,  0.25, -0.25,  1.0, 1.0,  // 0
-         0.25, -0.25, -0.25,  1.0, 0.0,  // 1
-        -0.25, -0.25, -0.25,  0.0, 0.0,  // 2
-        -0.25,  0.25, -0.25,  0.0, 1.0,  // 3
+    let vertices: [f32; 192] = [
+        // Positions      | Texture coords | Normals
+        -0.2, -0.2,  0.2,  0.0, 0.0,  0.0,  0.0,  1.0,  // Front
+         0.2, -0.2,  0.2,  1.0, 0.0,  0.0,  0.0,  1.0,
+         0.2,  0.2,  0.2,  1.0, 1.0,  0.0,  0.0,  1.0,
+        -0.2,  0.2,  0.2,  0.0, 1.0,  0.0,  0.0,  1.0,
+         0.2, -0.2, -0.2,  0.0, 0.0,  0.0,  0.0, -1.0,  // Back
+        -0.2, -0.2, -0.2,  1.0, 0.0,  0.0,  0.0, -1.0,
+        -0.2,  0.2, -0.2,  1.0, 1.0,  0.0,  0.0, -1.0,
+         0.2,  0.2, -0.2,  0.0, 1.0,  0.0,  0.0, -1.0,
+         0.2, -0.2,  0.2,  0.0, 0.0,  1.0,  0.0,  0.0,  // Right
+         0.2, -0.2, -0.2,  1.0, 0.0,  1.0,  0.0,  0.0,
+         0.2,  0.2, -0.2,  1.0, 1.0,  1.0,  0.0,  0.0,
+         0.2,  0.2,  0.2,  0.0, 1.0,  1.0,  0.0,  0.0,
+        -0.2, -0.2, -0.2,  0.0, 0.0, -1.0,  0.0,  0.0,  // Left
+        -0.2, -0.2,  0.2,  1.0, 0.0, -1.0,  0.0,  0.0,
+        -0.2,  0.2,  0.2,  1.0, 1.0, -1.0,  0.0,  0.0,
+        -0.2,  0.2, -0.2,  0.0, 1.0, -1.0,  0.0,  0.0,
+        -0.2,  0.2,  0.2,  0.0, 0.0,  0.0,  1.0,  0.0,  // Top
+         0.2,  0.2,  0.2,  1.0, 0.0,  0.0,  1.0,  0.0,
+         0.2,  0.2, -0.2,  1.0, 1.0,  0.0,  1.0,  0.0,
+        -0.2,  0.2, -0.2,  0.0, 1.0,  0.0,  1.0,  0.0,
+        -0.2, -0.2, -0.2,  0.0, 0.0,  0.0, -1.0,  0.0,  // Bottom
+         0.2, -0.2, -0.2,  1.0, 0.0,  0.0, -1.0,  0.0,
+         0.2, -0.2,  0.2,  1.0, 1.0,  0.0, -1.0,  0.0,
+        -0.2, -0.2,  0.2,  0.0, 1.0,  0.0, -1.0,  0.0,
     ];
 
     #[rustfmt::skip]
-    let indices: [i32; 6] = [
-        0, 1, 3,  // Indexes of verts
-        1, 2, 3,  // Indexes of verts
+    let indices: [i32; 36] = [
+         0,  1,  2,   0,  2,  3,  // Two triangles per face
+         4,  5,  6,   4,  6,  7,
+         8,  9, 10,   8, 10, 11,
+        12, 13, 14,  12, 14, 15,
+        16, 17, 18,  16, 18, 19,
+        20, 21, 22,  20, 22, 23,
     ];
 
     unsafe {
@@ -91,32 +207,26 @@ fn main() {
     VertexBuilder::bind_buffers(buffer, &indices, &mut vao)
         .attribute(3, gl::FLOAT)
         .attribute(2, gl::FLOAT)
-        .attribute(3, gl::FLOAT);
-
-    let texture_manager = {
-        let img = image::open(Path::new("./resources/textures/wall.jpg")).unwrap();
-        let img_2 = image::open(Path::new("./resources/textures/awesomeface.png")).unwrap();
-
-        let texture = TextureBuilder::new(img, gl::RGB, gl::RGB8)
-            .texture_parameter_i(gl::TEXTURE_WRAP_S, gl::MIRRORED_REPEAT)
-            .texture_parameter_i(gl::TEXTURE_WRAP_T, gl::MIRRORED_REPEAT)
-            .texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::NEAREST)
-            .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
-            .texture_storage(1)
-            .sub_texture(0, 0)
-            .build();
-
-        let texture_2 = TextureBuilder::new(img_2, gl::RGBA, gl::RGBA8)
-            .texture_storage(1)
-            .sub_texture(0, 0)
-            .build();
-
-        let mut texture_manager = TextureManager::new();
-        texture_manager.add_texture(texture);
-        texture_manager.add_texture(texture_2);
-
-        texture_manager
-    };
+        .attribute(3, gl::FLOAT)
+        .validate(shaders)
+        .validate(pbr_shader)
+        .validate(clustered_pbr_shader)
+        .validate(gbuffer_shader)
+        .validate(light_shader)
+        .validate(tile_shader)
+        .validate(gbuffer_tile_shader)
+        .validate(shadow_shader);
+
+    let mut texture_manager = TextureManager::new();
+
+    // Wrapping and anisotropy come from the sampler in `wall_material`
+    let wall_texture = texture_manager.load("textures/wall.jpg");
+    let face_texture = texture_manager.load("textures/awesomeface.png");
+
+    // The same image as an sRGB texture, for shaders that light in linear space. Its sampler is
+    // set on the PBR materials
+    let wall_srgb_texture =
+        texture_manager.load_with("textures/wall.jpg", TextureOptions::default().srgb());
 
     let cubes = [
         [0, 0, 0, 0, 0, 0, 0],
@@ -135,10 +245,220 @@ fn main() {
     let mut app = Application::new(&mut window, &events, camera);
 
     let mut matrix_block = UBO::new(3 * mem::size_of::<glm::Mat4>());
-    matrix_block.attach_new_shader(&shaders, "MatrixBlock");
-    matrix_block.attach_new_shader(&light_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(shaders, "MatrixBlock");
+    matrix_block.attach_new_shader(pbr_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(clustered_pbr_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(gbuffer_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(light_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(tile_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(gbuffer_tile_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(shadow_shader, "MatrixBlock");
+    matrix_block.attach_new_shader(point_shadow_shader, "MatrixBlock");
     matrix_block.bind();
-    texture_manager.bind_textures(0);
+
+    let light_buffer = LightBuffer::new();
+    light_buffer.attach_shader(shaders);
+    light_buffer.attach_shader(pbr_shader);
+    light_buffer.attach_shader(clustered_pbr_shader);
+    light_buffer.attach_shader(deferred_lighting_shader);
+
+    let shadows = Shadows::new(
+        ShadowSettings::default(),
+        shadow_shader,
+        point_shadow_shader,
+    );
+    shadows.attach_shader(shaders);
+    shadows.attach_shader(pbr_shader);
+    shadows.attach_shader(clustered_pbr_shader);
+    shadows.attach_shader(deferred_lighting_shader);
+
+    // Small colored lights circling the grid, enough to show the cost of many lights
+    let colored_lights = [
+        glm::vec3(1.0, 0.2, 0.2),
+        glm::vec3(0.2, 1.0, 0.2),
+        glm::vec3(0.2, 0.2, 1.0),
+        glm::vec3(1.0, 1.0, 0.2),
+        glm::vec3(0.2, 1.0, 1.0),
+        glm::vec3(1.0, 0.2, 1.0),
+    ];
+
+    let mut lights = Lights {
+        ambient: glm::vec3(0.1, 0.1, 0.1),
+        directional: vec![DirectionalLight {
+            direction: glm::vec3(-0.2, -1.0, -0.3),
+            color: glm::vec3(0.3, 0.3, 0.3),
+        }],
+        point: vec![PointLight {
+            position: glm::vec3(0.0, 0.0, 1.0),
+            color: glm::vec3(1.0, 0.8, 0.6),
+            attenuation: Attenuation::range(5.0),
+        }],
+        spot: vec![SpotLight {
+            position: glm::vec3(0.0, 0.0, 3.0),
+            direction: glm::vec3(0.0, 0.0, -1.0),
+            color: glm::vec3(0.8, 0.8, 0.8),
+            attenuation: Attenuation::range(10.0),
+            inner_cutoff: 12.5,
+            outer_cutoff: 17.5,
+        }],
+    };
+
+    let mut samplers = SamplerCache::new();
+    let mirrored = samplers.get(
+        &SamplerDesc::default()
+            .with_wrap(gl::MIRRORED_REPEAT)
+            .with_anisotropy(16.0),
+    );
+    let clamped = samplers.get(&SamplerDesc::default().with_wrap(gl::CLAMP_TO_EDGE));
+
+    let wall_material = Material::new(shaders)
+        .with_texture("ourTexture", &wall_texture)
+        .with_sampler("ourTexture", &mirrored)
+        .with_texture("ourFace", &face_texture)
+        .with_sampler("ourFace", &clamped)
+        .with_texture("directionalShadow", shadows.directional_texture())
+        .with_texture("pointShadow", shadows.point_texture())
+        .with("shininess", 32.0_f32)
+        .with("specularStrength", 0.5_f32);
+
+    let environment = Environment::from_equirect(
+        "textures/sky.hdr",
+        IblSettings::default(),
+        Some(&IblCache::from_env()),
+    );
+
+    let mut skybox = Skybox::new(skybox_shader, environment.environment());
+
+    let pbr_textures = PbrTextures::new().with_albedo(&wall_srgb_texture);
+    let pbr_material = pbr::material(pbr_shader, &pbr_textures, &environment)
+        .with_sampler("albedoMap", &mirrored)
+        .with_texture("directionalShadow", shadows.directional_texture())
+        .with_texture("pointShadow", shadows.point_texture());
+
+    // Metallic increases along x and roughness along y, in the order the grid is drawn
+    let cube_materials: Vec<Material> = (0..25)
+        .map(|index| {
+            pbr_material
+                .instance()
+                .with("metallicFactor", (index / 5) as f32 / 4.0)
+                .with("roughnessFactor", (index % 5) as f32 / 4.0)
+        })
+        .collect();
+
+    lights
+        .point
+        .extend(colored_lights.iter().map(|&color| PointLight {
+            position: glm::vec3(0.0, 0.0, 0.6),
+            color,
+            attenuation: Attenuation::range(1.5),
+        }));
+
+    let glass_material = pbr_material
+        .instance()
+        .with("albedoFactor", glm::vec4(0.6, 0.8, 1.0, 0.35))
+        .with("metallicFactor", 0.0_f32)
+        .with("roughnessFactor", 0.05_f32);
+
+    let gbuffer_material =
+        pbr::surface_material(gbuffer_shader, &pbr_textures).with_sampler("albedoMap", &mirrored);
+
+    let gbuffer_cube_materials: Vec<Material> = (0..25)
+        .map(|index| {
+            gbuffer_material
+                .instance()
+                .with("metallicFactor", (index / 5) as f32 / 4.0)
+                .with("roughnessFactor", (index % 5) as f32 / 4.0)
+        })
+        .collect();
+
+    let gbuffer_backdrop_material = gbuffer_material
+        .instance()
+        .with("metallicFactor", 0.0_f32)
+        .with("roughnessFactor", 0.9_f32);
+
+    let mut deferred_renderer = (render_path == RenderPath::Deferred).then(|| {
+        let (width, height) = app.framebuffer_size();
+        DeferredRenderer::new(
+            deferred_lighting_shader,
+            &environment,
+            &shadows,
+            width,
+            height,
+        )
+    });
+
+    // Hundreds of small lights drifting over the scene, only lit by the clustered renderer
+    let cluster_origins: Vec<Vec3> = (0..256)
+        .map(|index| {
+            let x = (index % 16) as f32 * 0.5 - 3.75;
+            let y = (index / 16) as f32 * 0.5 - 3.75;
+            glm::vec3(x, y, 0.35)
+        })
+        .collect();
+
+    let mut cluster_lights: Vec<ClusterLight> = (0..cluster_origins.len())
+        .map(|index| {
+            // Golden ratio steps spread the hues evenly
+            let hue = (index as f32 * 0.618_034).fract() * 6.0;
+            let color = glm::vec3(
+                (hue - 3.0).abs() - 1.0,
+                2.0 - (hue - 2.0).abs(),
+                2.0 - (hue - 4.0).abs(),
+            );
+
+            ClusterLight {
+                position: cluster_origins[index],
+                color: color.map(|channel| channel.clamp(0.0, 1.0) * 2.0),
+                range: 0.9,
+            }
+        })
+        .collect();
+
+    let mut clustered_lights = (render_path == RenderPath::Clustered).then(|| {
+        let clustered_lights = ClusteredLights::new(ClusterGrid::default(), cluster_lights.len());
+        clustered_lights.attach_shader(clustered_pbr_shader);
+        clustered_lights
+    });
+
+    // The clustered point lights have no shadow map, only the directional light is shadowed
+    let clustered_material = pbr::material(clustered_pbr_shader, &pbr_textures, &environment)
+        .with_sampler("albedoMap", &mirrored)
+        .with_texture("directionalShadow", shadows.directional_texture());
+
+    let clustered_cube_materials: Vec<Material> = (0..25)
+        .map(|index| {
+            clustered_material
+                .instance()
+                .with("metallicFactor", (index / 5) as f32 / 4.0)
+                .with("roughnessFactor", (index % 5) as f32 / 4.0)
+        })
+        .collect();
+
+    let clustered_backdrop_material = clustered_material
+        .instance()
+        .with("metallicFactor", 0.0_f32)
+        .with("roughnessFactor", 0.9_f32);
+
+    // One array layer per tile, so a single texture serves the whole strip
+    let tile_set = TextureBuilder::array(
+        ["textures/wall.jpg", "textures/awesomeface.png"]
+            .iter()
+            .map(|path| image::DynamicImage::ImageRgba8(assets::image(path).unwrap().to_rgba8()))
+            .collect(),
+    )
+    .trilinear()
+    .mipmapped_storage()
+    .sub_texture(0, 0)
+    .generate_mipmaps()
+    .build();
+    let tile_set = texture_manager.insert("tiles", tile_set);
+
+    let mut tile_material = Material::new(tile_shader).with_texture("tiles", &tile_set);
+    let mut gbuffer_tile_material =
+        Material::new(gbuffer_tile_shader).with_texture("tiles", &tile_set);
+
+    let mut light_material =
+        Material::new(light_shader).with("objectColor", glm::vec3(1.0, 1.0, 1.0));
 
     let player_pos = Vec3::new(0.0, 0.0, 0.0);
 
@@ -149,14 +469,29 @@ fn main() {
 
         app.handle_window_event(&mut proj, delta as f32);
 
-        matrix_block.next_attribute(&proj);
-        matrix_block.next_attribute(&app.view());
+        let view = app.view();
 
-        app.clear();
-        shaders.use_program();
-        app.bind_vao(vao);
+        // The point light circles the grid, the spot light follows the camera like a flashlight
+        let time = current_time as f32;
+        lights.point[0].position = glm::vec3(time.cos() * 1.2, time.sin() * 1.2, 1.0);
+
+        for (index, light) in lights.point.iter_mut().enumerate().skip(1) {
+            let angle = -time * 0.5 + index as f32 * 2.0 * PI / colored_lights.len() as f32;
+            light.position = glm::vec3(angle.cos() * 1.6, angle.sin() * 1.6, 0.6);
+        }
+
+        for (index, light) in cluster_lights.iter_mut().enumerate() {
+            let phase = time + index as f32 * 0.7;
+            light.position =
+                cluster_origins[index] + glm::vec3(phase.cos(), phase.sin(), 0.0) * 0.25;
+        }
+
+        lights.spot[0].position = app.camera_position();
+        lights.spot[0].direction = app.camera_front();
+        light_buffer.upload(&lights, &app.camera_position());
 
         let cubes = &cubes[player_pos.x as usize..player_pos.x as usize + 5];
+        let mut models = Vec::new();
 
         cubes.iter().enumerate().for_each(|(index, sub_cubes)| {
             let index_isize = index as isize - 2;
@@ -173,17 +508,176 @@ fn main() {
                 let position = Vec3::new(x, y, 0.0);
 
                 model = glm::translate(&model, &position);
+                models.push(model);
+            });
+        });
+
+        // A flat backdrop behind the grid to catch the shadows
+        let backdrop = glm::translate(&glm::Mat4::identity(), &glm::vec3(0.0, 0.0, -0.8));
+        let backdrop = glm::scale(&backdrop, &glm::vec3(10.0, 10.0, 0.25));
+        models.push(backdrop);
+
+        let draw_scene = |matrix_block: &mut UBO| {
+            for model in &models {
+                matrix_block.next_attribute_reduced(model);
+                app.draw(36);
+            }
+        };
+
+        // A strip of tiles below the grid, opaque, so the deferred path puts it in the G-buffer
+        let draw_tiles = |material: &mut Material, matrix_block: &mut UBO| {
+            for tile in 0..5 {
+                material.set("tileId", (tile % 2) as u32);
+                material.apply();
+
+                let position = glm::vec3(tile as f32 * 0.5 - 1.0, -1.6, 0.0);
+                let model = glm::translate(&glm::Mat4::identity(), &position);
                 matrix_block.next_attribute_reduced(&model);
+                app.draw(36);
+            }
+        };
 
-                app.draw(6);
-            });
+        app.bind_vao(vao);
+
+        match (deferred_renderer.as_mut(), clustered_lights.as_mut()) {
+            (None, None) => {
+                shadows.render(&lights, &view, &proj, &mut matrix_block, draw_scene);
+
+                matrix_block.next_attribute(&proj);
+                matrix_block.next_attribute(&view);
+
+                app.clear();
+
+                for (model, material) in models.iter().zip(&cube_materials) {
+                    material.apply();
+                    matrix_block.next_attribute_reduced(model);
+                    app.draw(36);
+                }
+
+                wall_material.apply();
+                matrix_block.next_attribute_reduced(&backdrop);
+                app.draw(36);
+
+                draw_tiles(&mut tile_material, &mut matrix_block);
+            }
+            (Some(renderer), _) => {
+                shadows.render(&lights, &view, &proj, &mut matrix_block, draw_scene);
+
+                matrix_block.next_attribute(&proj);
+                matrix_block.next_attribute(&view);
+
+                let (width, height) = app.framebuffer_size();
+                renderer.begin_geometry(width, height);
+
+                for (model, material) in models.iter().zip(&gbuffer_cube_materials) {
+                    material.apply();
+                    matrix_block.next_attribute_reduced(model);
+                    app.draw(36);
+                }
+
+                gbuffer_backdrop_material.apply();
+                matrix_block.next_attribute_reduced(&backdrop);
+                app.draw(36);
+
+                draw_tiles(&mut gbuffer_tile_material, &mut matrix_block);
+
+                renderer.light(&view, &proj);
+            }
+            (None, Some(clusters)) => {
+                shadows.render(&lights, &view, &proj, &mut matrix_block, draw_scene);
+
+                let (width, height) = app.framebuffer_size();
+                clusters.update(&cluster_lights, &view, &proj, width, height);
+
+                matrix_block.next_attribute(&proj);
+                matrix_block.next_attribute(&view);
+
+                app.clear();
+
+                for (model, material) in models.iter().zip(&clustered_cube_materials) {
+                    material.apply();
+                    matrix_block.next_attribute_reduced(model);
+                    app.draw(36);
+                }
+
+                clustered_backdrop_material.apply();
+                matrix_block.next_attribute_reduced(&backdrop);
+                app.draw(36);
+
+                draw_tiles(&mut tile_material, &mut matrix_block);
+            }
+        }
+
+        skybox.draw(&view, &proj);
+
+        // Transparent objects are always forward rendered, back to front over the opaque depth
+        let glass = glm::translate(&glm::Mat4::identity(), &glm::vec3(-0.5, 0.0, 0.45));
+        let glass = glm::scale(&glass, &glm::vec3(5.0, 6.5, 0.1));
+        let mut transparent = [(glass, &glass_material)];
+
+        let camera_position = app.camera_position();
+        transparent.sort_by(|(a, _), (b, _)| {
+            let distance = |model: &Mat4| glm::distance2(&model.column(3).xyz(), &camera_position);
+            distance(b).total_cmp(&distance(a))
         });
 
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+        }
+
+        for (model, material) in &transparent {
+            material.apply();
+            matrix_block.next_attribute_reduced(model);
+            app.draw(36);
+        }
+
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+
+        // The clustered renderer ignores the point lights in `LightBlock`
+        let emitters: Vec<(Vec3, Vec3)> = if clustered_lights.is_some() {
+            cluster_lights
+                .iter()
+                .map(|light| (light.position, light.color))
+                .collect()
+        } else {
+            lights
+                .point
+                .iter()
+                .map(|light| (light.position, light.color))
+                .collect()
+        };
+
+        for (position, color) in &emitters {
+            light_material.set("lightColor", *color);
+            light_material.apply();
+
+            let model = glm::translate(&glm::Mat4::identity(), position);
+            let model = glm::scale(&model, &glm::vec3(0.25, 0.25, 0.25));
+            matrix_block.next_attribute_reduced(&model);
+            app.draw(36);
+        }
+
         matrix_block.clear();
         app.finish_frame();
     }
 }
 
+#[cfg(feature = "check-shaders")]
+fn check_shaders(args: &[std::ffi::OsString]) -> i32 {
+    shader_check::run(args)
+}
+
+#[cfg(not(feature = "check-shaders"))]
+fn check_shaders(_args: &[std::ffi::OsString]) -> i32 {
+    eprintln!("check-shaders needs the `check-shaders` feature");
+    2
+}
+
 pub fn clamp(value: usize, min: usize, max: usize) -> usize {
     if value < min {
         min
@@ -266,6 +760,18 @@ impl Application<'_> {
         self.camera.view()
     }
 
+    fn framebuffer_size(&self) -> (i32, i32) {
+        self.window.get_framebuffer_size()
+    }
+
+    fn camera_position(&self) -> Vec3 {
+        self.camera.position()
+    }
+
+    fn camera_front(&self) -> Vec3 {
+        self.camera.front()
+    }
+
     fn key_presses(&mut self, speed: f32) {
         if self.keys.contains(&Key::W) {
             self.camera.forward(speed);
@@ -289,9 +795,9 @@ impl Application<'_> {
         }
     }
 
+    /// The skybox is drawn over whatever color stays uncovered
     fn clear(&self) {
         unsafe {
-            gl::ClearColor(0.2, 0.3, 0.3, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
     }
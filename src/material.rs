@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use nalgebra_glm::{IVec2, IVec3, IVec4, Mat3, Mat4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4};
+use opengl::gl;
+
+use crate::samplers::SamplerHandle;
+use crate::shaders::{types, Shader};
+use crate::textures::TextureHandle;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MaterialValue {
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    Int(i32),
+    IVec2(IVec2),
+    IVec3(IVec3),
+    IVec4(IVec4),
+    UInt(u32),
+    UVec2(UVec2),
+    UVec3(UVec3),
+    UVec4(UVec4),
+    Bool(bool),
+    Mat3(Mat3),
+    Mat4(Mat4),
+}
+
+impl MaterialValue {
+    fn gl_type(&self) -> gl::types::GLenum {
+        match self {
+            MaterialValue::Float(_) => gl::FLOAT,
+            MaterialValue::Vec2(_) => gl::FLOAT_VEC2,
+            MaterialValue::Vec3(_) => gl::FLOAT_VEC3,
+            MaterialValue::Vec4(_) => gl::FLOAT_VEC4,
+            MaterialValue::Int(_) => gl::INT,
+            MaterialValue::IVec2(_) => gl::INT_VEC2,
+            MaterialValue::IVec3(_) => gl::INT_VEC3,
+            MaterialValue::IVec4(_) => gl::INT_VEC4,
+            MaterialValue::UInt(_) => gl::UNSIGNED_INT,
+            MaterialValue::UVec2(_) => gl::UNSIGNED_INT_VEC2,
+            MaterialValue::UVec3(_) => gl::UNSIGNED_INT_VEC3,
+            MaterialValue::UVec4(_) => gl::UNSIGNED_INT_VEC4,
+            MaterialValue::Bool(_) => gl::BOOL,
+            MaterialValue::Mat3(_) => gl::FLOAT_MAT3,
+            MaterialValue::Mat4(_) => gl::FLOAT_MAT4,
+        }
+    }
+
+    fn apply(&self, shader: &Shader, name: &str) {
+        match self {
+            MaterialValue::Float(value) => shader.set_uniform(name, *value),
+            MaterialValue::Vec2(value) => shader.set_uniform(name, value),
+            MaterialValue::Vec3(value) => shader.set_uniform(name, value),
+            MaterialValue::Vec4(value) => shader.set_uniform(name, value),
+            MaterialValue::Int(value) => shader.set_uniform(name, *value),
+            MaterialValue::IVec2(value) => shader.set_uniform(name, value),
+            MaterialValue::IVec3(value) => shader.set_uniform(name, value),
+            MaterialValue::IVec4(value) => shader.set_uniform(name, value),
+            MaterialValue::UInt(value) => shader.set_uniform(name, *value),
+            MaterialValue::UVec2(value) => shader.set_uniform(name, value),
+            MaterialValue::UVec3(value) => shader.set_uniform(name, value),
+            MaterialValue::UVec4(value) => shader.set_uniform(name, value),
+            MaterialValue::Bool(value) => shader.set_uniform(name, *value),
+            MaterialValue::Mat3(value) => shader.set_uniform(name, value),
+            MaterialValue::Mat4(value) => shader.set_uniform(name, value),
+        }
+    }
+}
+
+macro_rules! material_value_from {
+    ($($ty:ty => $variant:ident),* $(,)?) => {$(
+        impl From<$ty> for MaterialValue {
+            fn from(value: $ty) -> Self {
+                MaterialValue::$variant(value)
+            }
+        }
+    )*};
+}
+
+material_value_from! {
+    f32 => Float,
+    Vec2 => Vec2,
+    Vec3 => Vec3,
+    Vec4 => Vec4,
+    i32 => Int,
+    IVec2 => IVec2,
+    IVec3 => IVec3,
+    IVec4 => IVec4,
+    u32 => UInt,
+    UVec2 => UVec2,
+    UVec3 => UVec3,
+    UVec4 => UVec4,
+    bool => Bool,
+    Mat3 => Mat3,
+    Mat4 => Mat4,
+}
+
+/// A shader together with its uniform values and textures, applied with one call before drawing
+pub struct Material<'a> {
+    shader: &'a Shader,
+    parent: Option<&'a Material<'a>>,
+    parameters: HashMap<String, MaterialValue>,
+    /// Texture unit to texture, the units come from the sampler bindings of the shader. The
+    /// handles keep the textures alive as long as the material
+    textures: HashMap<u32, TextureHandle>,
+    /// Texture unit to sampler object, units without one use the texture's own parameters
+    samplers: HashMap<u32, SamplerHandle>,
+}
+
+#[allow(dead_code)]
+impl<'a> Material<'a> {
+    pub fn new(shader: &'a Shader) -> Material<'a> {
+        Material {
+            shader,
+            parent: None,
+            parameters: HashMap::new(),
+            textures: HashMap::new(),
+            samplers: HashMap::new(),
+        }
+    }
+
+    /// A material sharing everything with `self`, except what gets set on the instance
+    pub fn instance(&'a self) -> Material<'a> {
+        Material {
+            parent: Some(self),
+            ..Material::new(self.shader)
+        }
+    }
+
+    pub fn shader(&self) -> &'a Shader {
+        self.shader
+    }
+
+    /// Panics if `name` is active in the shader with a different type
+    pub fn set(&mut self, name: &str, value: impl Into<MaterialValue>) -> &mut Self {
+        let value = value.into();
+
+        match self.shader.reflection().uniform(name) {
+            Some(uniform) => assert!(
+                uniform.gl_type == value.gl_type(),
+                "Material parameter `{name}` is {}, but the shader declares {}",
+                types::type_name(value.gl_type()),
+                types::type_name(uniform.gl_type)
+            ),
+            None => {
+                if self.shader.note_missing_uniform(name) {
+                    println!(
+                        "Warning: material parameter `{name}` is not active in program {}",
+                        self.shader.id
+                    );
+                }
+            }
+        }
+
+        self.parameters.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn with(mut self, name: &str, value: impl Into<MaterialValue>) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MaterialValue> {
+        self.parameters
+            .get(name)
+            .or_else(|| self.parent.and_then(|parent| parent.get(name)))
+    }
+
+    /// Binds `texture` to the unit of the sampler `name`, panics if the shader has no such sampler
+    pub fn set_texture(&mut self, name: &str, texture: &TextureHandle) -> &mut Self {
+        let Some(sampler) = self.shader.reflection().sampler(name) else {
+            panic!(
+                "Material texture `{name}` is not an active sampler in program {}",
+                self.shader.id
+            );
+        };
+
+        self.textures
+            .insert(sampler.binding as u32, texture.clone());
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str, texture: &TextureHandle) -> Self {
+        self.set_texture(name, texture);
+        self
+    }
+
+    /// Samples the texture of `name` with a sampler object, panics if the shader has no such
+    /// sampler
+    pub fn set_sampler(&mut self, name: &str, sampler: &SamplerHandle) -> &mut Self {
+        let Some(uniform) = self.shader.reflection().sampler(name) else {
+            panic!(
+                "Material sampler `{name}` is not an active sampler in program {}",
+                self.shader.id
+            );
+        };
+
+        self.samplers
+            .insert(uniform.binding as u32, sampler.clone());
+        self
+    }
+
+    pub fn with_sampler(mut self, name: &str, sampler: &SamplerHandle) -> Self {
+        self.set_sampler(name, sampler);
+        self
+    }
+
+    fn sampler(&self, unit: u32) -> u32 {
+        match self.samplers.get(&unit) {
+            Some(sampler) => sampler.id(),
+            None => self.parent.map_or(0, |parent| parent.sampler(unit)),
+        }
+    }
+
+    /// Uses the program and uploads every parameter and texture, parents first
+    pub fn apply(&self) {
+        self.shader.use_program();
+        self.apply_values();
+
+        // Every sampler unit of the shader gets its sampler rebound, 0 where the material has
+        // none, or the one of the previous material would stick
+        for sampler in &self.shader.reflection().samplers {
+            let unit = sampler.binding as u32;
+            unsafe {
+                gl::BindSampler(unit, self.sampler(unit));
+            }
+        }
+    }
+
+    fn apply_values(&self) {
+        if let Some(parent) = self.parent {
+            parent.apply_values();
+        }
+
+        for (name, value) in &self.parameters {
+            value.apply(self.shader, name);
+        }
+
+        for (&unit, texture) in &self.textures {
+            texture.bind(unit);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use nalgebra_glm as glm;
+
+use crate::ibl::Environment;
+use crate::material::Material;
+use crate::shaders::Shader;
+use crate::textures::{TextureBuilder, TextureHandle};
+
+/// The maps of a metallic-roughness material, every map not given is a 1x1 texture that leaves
+/// its factor unchanged
+#[derive(Clone, Debug)]
+pub struct PbrTextures {
+    /// Expected in an sRGB format, so sampling returns linear colors
+    pub albedo: TextureHandle,
+    /// Roughness in green and metallic in blue, like glTF
+    pub metallic_roughness: TextureHandle,
+    pub normal: TextureHandle,
+    pub occlusion: TextureHandle,
+    pub emissive: TextureHandle,
+}
+
+#[allow(dead_code)]
+impl PbrTextures {
+    pub fn new() -> PbrTextures {
+        let white = solid([255, 255, 255, 255], false);
+
+        PbrTextures {
+            albedo: solid([255, 255, 255, 255], true),
+            metallic_roughness: white.clone(),
+            normal: solid([128, 128, 255, 255], false),
+            occlusion: white.clone(),
+            // Multiplied by `emissiveFactor`, which defaults to black
+            emissive: white,
+        }
+    }
+
+    pub fn with_albedo(mut self, texture: &TextureHandle) -> Self {
+        self.albedo = texture.clone();
+        self
+    }
+
+    pub fn with_metallic_roughness(mut self, texture: &TextureHandle) -> Self {
+        self.metallic_roughness = texture.clone();
+        self
+    }
+
+    pub fn with_normal(mut self, texture: &TextureHandle) -> Self {
+        self.normal = texture.clone();
+        self
+    }
+
+    pub fn with_occlusion(mut self, texture: &TextureHandle) -> Self {
+        self.occlusion = texture.clone();
+        self
+    }
+
+    pub fn with_emissive(mut self, texture: &TextureHandle) -> Self {
+        self.emissive = texture.clone();
+        self
+    }
+}
+
+/// A material for `pbr.frag` with neutral factors, override them with `Material::set`
+pub fn material<'a>(
+    shader: &'a Shader,
+    textures: &PbrTextures,
+    environment: &Environment,
+) -> Material<'a> {
+    with_environment(surface_material(shader, textures), environment)
+}
+
+/// Only the surface inputs, for shaders that don't light like `gbuffer.frag`
+pub fn surface_material<'a>(shader: &'a Shader, textures: &PbrTextures) -> Material<'a> {
+    Material::new(shader)
+        .with_texture("albedoMap", &textures.albedo)
+        .with_texture("metallicRoughnessMap", &textures.metallic_roughness)
+        .with_texture("normalMap", &textures.normal)
+        .with_texture("occlusionMap", &textures.occlusion)
+        .with_texture("emissiveMap", &textures.emissive)
+        .with("albedoFactor", glm::vec4(1.0, 1.0, 1.0, 1.0))
+        .with("metallicFactor", 1.0_f32)
+        .with("roughnessFactor", 1.0_f32)
+        .with("normalScale", 1.0_f32)
+        .with("occlusionStrength", 1.0_f32)
+        .with("emissiveFactor", glm::vec3(0.0, 0.0, 0.0))
+}
+
+/// Adds the image based lighting inputs, shared by the forward and the `DEFERRED` variant of
+/// `pbr.frag`
+pub fn with_environment<'a>(material: Material<'a>, environment: &Environment) -> Material<'a> {
+    material
+        .with_texture("irradianceMap", environment.irradiance())
+        .with_texture("prefilterMap", environment.prefiltered())
+        .with_texture("brdfLut", environment.brdf_lut())
+        .with("prefilterLevels", environment.prefilter_levels() as f32)
+}
+
+fn solid(color: [u8; 4], srgb: bool) -> TextureHandle {
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba(color)));
+
+    let texture = TextureBuilder::new(image)
+        .try_srgb(srgb)
+        .expect("RGBA8 has an sRGB format")
+        .texture_storage(1)
+        .sub_texture(0, 0)
+        .build();
+
+    TextureHandle::new(texture, &format!("solid {color:?}"))
+}
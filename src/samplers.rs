@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use opengl::gl;
+
+use crate::textures;
+
+/// How a texture is sampled, independent of the texture itself
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    /// S, T and R wrap modes
+    pub wrap: [gl::types::GLenum; 3],
+    pub min_filter: gl::types::GLenum,
+    pub mag_filter: gl::types::GLenum,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub lod_bias: f32,
+    /// Used by `CLAMP_TO_BORDER`
+    pub border_color: [f32; 4],
+    /// Depth comparison function for shadow samplers, `None` returns the raw depth
+    pub compare: Option<gl::types::GLenum>,
+    /// Clamped to the hardware maximum when the sampler is created
+    pub anisotropy: f32,
+}
+
+impl Default for SamplerDesc {
+    /// The GL defaults, except trilinear filtering
+    fn default() -> Self {
+        SamplerDesc {
+            wrap: [gl::REPEAT; 3],
+            min_filter: gl::LINEAR_MIPMAP_LINEAR,
+            mag_filter: gl::LINEAR,
+            min_lod: -1000.0,
+            max_lod: 1000.0,
+            lod_bias: 0.0,
+            border_color: [0.0; 4],
+            compare: None,
+            anisotropy: 1.0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl SamplerDesc {
+    pub fn with_wrap(mut self, wrap: gl::types::GLenum) -> Self {
+        self.wrap = [wrap; 3];
+        self
+    }
+
+    pub fn with_filter(mut self, min: gl::types::GLenum, mag: gl::types::GLenum) -> Self {
+        self.min_filter = min;
+        self.mag_filter = mag;
+        self
+    }
+
+    /// Nearest texel from the base level, for pixel art and lookup tables
+    pub fn nearest(self) -> Self {
+        self.with_filter(gl::NEAREST, gl::NEAREST)
+    }
+
+    pub fn with_lod(mut self, min: f32, max: f32, bias: f32) -> Self {
+        self.min_lod = min;
+        self.max_lod = max;
+        self.lod_bias = bias;
+        self
+    }
+
+    pub fn with_border_color(mut self, color: [f32; 4]) -> Self {
+        self.border_color = color;
+        self
+    }
+
+    pub fn with_compare(mut self, func: gl::types::GLenum) -> Self {
+        self.compare = Some(func);
+        self
+    }
+
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    /// Floats compared by their bits, so the description can be a map key
+    fn key(&self) -> [u32; 15] {
+        let [s, t, r] = self.wrap;
+        let [red, green, blue, alpha] = self.border_color.map(f32::to_bits);
+
+        [
+            s,
+            t,
+            r,
+            self.min_filter,
+            self.mag_filter,
+            self.min_lod.to_bits(),
+            self.max_lod.to_bits(),
+            self.lod_bias.to_bits(),
+            red,
+            green,
+            blue,
+            alpha,
+            self.compare.is_some() as u32,
+            self.compare.unwrap_or(0),
+            self.anisotropy.to_bits(),
+        ]
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+pub struct Sampler(u32);
+
+#[allow(dead_code)]
+impl Sampler {
+    pub fn new(desc: &SamplerDesc) -> Sampler {
+        let mut sampler = 0;
+
+        unsafe {
+            gl::CreateSamplers(1, &mut sampler);
+
+            let [s, t, r] = desc.wrap;
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, s as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, t as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_R, r as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, desc.min_filter as i32);
+            gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, desc.mag_filter as i32);
+            gl::SamplerParameterf(sampler, gl::TEXTURE_MIN_LOD, desc.min_lod);
+            gl::SamplerParameterf(sampler, gl::TEXTURE_MAX_LOD, desc.max_lod);
+            gl::SamplerParameterf(sampler, gl::TEXTURE_LOD_BIAS, desc.lod_bias);
+            gl::SamplerParameterfv(
+                sampler,
+                gl::TEXTURE_BORDER_COLOR,
+                desc.border_color.as_ptr(),
+            );
+
+            match desc.compare {
+                Some(func) => {
+                    gl::SamplerParameteri(
+                        sampler,
+                        gl::TEXTURE_COMPARE_MODE,
+                        gl::COMPARE_REF_TO_TEXTURE as i32,
+                    );
+                    gl::SamplerParameteri(sampler, gl::TEXTURE_COMPARE_FUNC, func as i32);
+                }
+                None => gl::SamplerParameteri(sampler, gl::TEXTURE_COMPARE_MODE, gl::NONE as i32),
+            }
+
+            if desc.anisotropy > 1.0 {
+                gl::SamplerParameterf(
+                    sampler,
+                    gl::TEXTURE_MAX_ANISOTROPY,
+                    desc.anisotropy.min(textures::max_anisotropy()),
+                );
+            }
+        }
+
+        Sampler(sampler)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSamplers(1, &self.0);
+        }
+    }
+}
+
+/// A sampler from a `SamplerCache`. Clones share the sampler, it is deleted with the last
+/// handle, so materials can outlive the cache
+#[derive(Clone)]
+pub struct SamplerHandle(Rc<Sampler>);
+
+impl SamplerHandle {
+    pub fn id(&self) -> u32 {
+        self.0.id()
+    }
+}
+
+impl PartialEq for SamplerHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SamplerHandle {}
+
+/// Creates each distinct sampler once and hands out shared handles to it
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, SamplerHandle>,
+}
+
+#[allow(dead_code)]
+impl SamplerCache {
+    pub fn new() -> SamplerCache {
+        SamplerCache {
+            samplers: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, desc: &SamplerDesc) -> SamplerHandle {
+        self.samplers
+            .entry(*desc)
+            .or_insert_with(|| SamplerHandle(Rc::new(Sampler::new(desc))))
+            .clone()
+    }
+}
@@ -0,0 +1,618 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use naga::front::glsl::{Frontend, Options};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::ShaderStage;
+
+use crate::assets::{self, ASSETS_FLAG};
+use crate::deferred::RENDERER_FLAG;
+use crate::shaders::{Defines, PERMUTATIONS};
+
+/// The app's own flags that take a value, skipped along with it
+const VALUE_FLAGS: &[&str] = &[ASSETS_FLAG, RENDERER_FLAG];
+
+/// `check-shaders [dir] [--define NAME[=VALUE]]...`, parses and validates every shader in each
+/// permutation the app compiles without a GL context and returns the process exit code
+pub fn run(args: &[OsString]) -> i32 {
+    let mut dir = None;
+    let mut defines = Defines::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+
+        if arg == "--define" || arg == "-D" {
+            let Some(define) = args.next() else {
+                eprintln!("{arg} needs a NAME[=VALUE] argument");
+                return 2;
+            };
+            defines = add_define(defines, &define.to_string_lossy());
+        } else if let Some(define) = arg.strip_prefix("-D") {
+            defines = add_define(defines, define);
+        } else if arg.starts_with("--") {
+            // `--assets` and `--renderer` belong to the app, other flags are ignored on their own
+            if VALUE_FLAGS.contains(&arg.as_ref()) {
+                args.next();
+            }
+        } else {
+            dir = Some(PathBuf::from(arg.as_ref()));
+        }
+    }
+
+    let files = match shader_files(dir.as_deref()) {
+        Ok(files) => files,
+        Err(error) => {
+            eprintln!("{error}");
+            return 2;
+        }
+    };
+
+    let mut errors = 0;
+
+    for (path, source) in &files {
+        let Some(stage) = stage(path) else {
+            println!("{path}: skipped, unknown shader stage");
+            continue;
+        };
+
+        for flag in permutations(path) {
+            let (defines, label) = match flag {
+                Some(flag) => (defines.clone().flag(flag), format!(" (-D {flag})")),
+                None => (defines.clone(), String::new()),
+            };
+
+            let diagnostics = check(source, stage, &defines);
+            if diagnostics.is_empty() {
+                println!("{path}{label}: ok");
+            }
+
+            for (line, message) in diagnostics {
+                eprintln!("{path}:{line}: error: {message}{label}");
+                errors += 1;
+            }
+        }
+    }
+
+    for mismatch in block_mismatches(&files) {
+        eprintln!("{mismatch}");
+        errors += 1;
+    }
+
+    if errors > 0 {
+        eprintln!("{errors} error(s) in {} shader(s)", files.len());
+        1
+    } else {
+        println!("{} shader(s) ok", files.len());
+        0
+    }
+}
+
+/// One error for every interface block declared differently from the other files declaring it
+fn block_mismatches(files: &[(String, String)]) -> Vec<String> {
+    let mut blocks = BTreeMap::new();
+    for (path, source) in files {
+        for block in shared_blocks(source) {
+            blocks
+                .entry(block.name.clone())
+                .or_insert_with(Vec::new)
+                .push((path, block));
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for (name, declarations) in &blocks {
+        // The odd one out is the file to fix, so compare against the most common declaration,
+        // the first one on a tie
+        let count = |block: &SharedBlock| {
+            declarations
+                .iter()
+                .filter(|(_, other)| other.declaration == block.declaration)
+                .count()
+        };
+        let (_, (expected_path, expected)) = declarations
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, (_, block))| (count(block), Reverse(*index)))
+            .unwrap();
+
+        for (path, block) in declarations {
+            if block.declaration != expected.declaration {
+                let error = format!(
+                    "{path}:{}: error: `{name}` differs from the declaration at {expected_path}:{}",
+                    block.line, expected.line
+                );
+                mismatches.push(format!(
+                    "{error}\n    {}\n    {}",
+                    block.declaration, expected.declaration
+                ));
+            }
+        }
+    }
+
+    mismatches
+}
+
+fn add_define(defines: Defines, define: &str) -> Defines {
+    match define.split_once('=') {
+        Some((name, value)) => defines.define(name, value),
+        None => defines.flag(define),
+    }
+}
+
+/// Reads `dir`, or `shaders/` from the asset roots, or the embedded shaders, in that order
+fn shader_files(dir: Option<&Path>) -> Result<Vec<(String, String)>, String> {
+    let dir = match dir {
+        Some(dir) => Some(dir.to_path_buf()),
+        None => assets::get().resolve_dir("shaders"),
+    };
+
+    let Some(dir) = dir else {
+        return Ok(assets::embedded_files("shaders/")
+            .map(|(path, bytes)| {
+                (
+                    path.to_string(),
+                    String::from_utf8_lossy(bytes).into_owned(),
+                )
+            })
+            .collect());
+    };
+
+    let entries = fs::read_dir(&dir).map_err(|error| format!("{}: {error}", dir.display()))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|error| error.to_string())?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let source =
+            fs::read_to_string(&path).map_err(|error| format!("{}: {error}", path.display()))?;
+        files.push((path.display().to_string(), source));
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// `None` for the shader as is, then every flag of `PERMUTATIONS` for its file name, each one
+/// checked on top of the `--define`s
+fn permutations(path: &str) -> Vec<Option<&'static str>> {
+    let file_name = Path::new(path).file_name().and_then(|name| name.to_str());
+    let flags = PERMUTATIONS
+        .iter()
+        .find(|(file, _)| Some(*file) == file_name)
+        .map_or(&[][..], |(_, flags)| flags);
+
+    std::iter::once(None)
+        .chain(flags.iter().copied().map(Some))
+        .collect()
+}
+
+fn stage(path: &str) -> Option<ShaderStage> {
+    match Path::new(path).extension()?.to_str()? {
+        "vert" => Some(ShaderStage::Vertex),
+        "frag" => Some(ShaderStage::Fragment),
+        "comp" => Some(ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+/// Returns `(line, message)` for every parse or validation error
+fn check(source: &str, stage: ShaderStage, defines: &Defines) -> Vec<(u32, String)> {
+    // naga defines the macros itself, which keeps the reported lines those of the file
+    let mut options = Options::from(stage);
+    for (name, value) in defines.iter() {
+        options.defines.insert(name.to_string(), value.to_string());
+    }
+
+    let (source, aliases) = vulkan_glsl(source);
+    for (name, value) in aliases {
+        options.defines.entry(name).or_insert(value);
+    }
+    let source = source.as_str();
+
+    let module = match Frontend::default().parse(&options, source) {
+        Ok(module) => module,
+        Err(errors) => {
+            return errors
+                .into_iter()
+                .map(|error| {
+                    (
+                        error.meta.location(source).line_number,
+                        error.kind.to_string(),
+                    )
+                })
+                .collect();
+        }
+    };
+
+    // GLSL leaves derivatives in non-uniform control flow undefined instead of an error
+    let flags = ValidationFlags::all() - ValidationFlags::CONTROL_FLOW_UNIFORMITY;
+    let mut validator = Validator::new(flags, Capabilities::all());
+    match validator.validate(&module) {
+        Ok(_) => Vec::new(),
+        Err(error) => {
+            let line = error
+                .spans()
+                .next()
+                .map_or(0, |(span, _)| span.location(source).line_number);
+            vec![(line, error.as_inner().to_string())]
+        }
+    }
+}
+
+// GL keeps uniform blocks, storage blocks, texture units and image units apart while naga has
+// one binding space per set, so each kind gets its own set
+const SAMPLER_SET: u32 = 1;
+const LOOSE_UNIFORM_SET: u32 = 2;
+const STORAGE_SET: u32 = 3;
+const TEXTURE_SET: u32 = 4;
+const IMAGE_SET: u32 = 5;
+
+/// naga only reads Vulkan flavoured GLSL. Loose uniforms become one block each, combined
+/// samplers a texture and a sampler with a macro that combines them again, varyings without a
+/// location get the next free one, like the GL linker would, and the GL vertex inputs are
+/// macros for the Vulkan names. Every declaration stays on its line
+fn vulkan_glsl(source: &str) -> (String, Vec<(String, String)>) {
+    let source = strip_comments(source);
+    let mut aliases = vec![
+        ("gl_VertexID".to_string(), "gl_VertexIndex".to_string()),
+        ("gl_InstanceID".to_string(), "gl_InstanceIndex".to_string()),
+    ];
+    let mut blocks = 0;
+
+    // The next free location of the inputs and of the outputs
+    let mut locations = [0, 0];
+    for line in source.lines() {
+        let (qualifiers, rest) = layout(line);
+        if let (Some(location), Some(direction)) =
+            (qualifier(qualifiers, "location"), varying(rest))
+        {
+            locations[direction] = locations[direction].max(location + 1);
+        }
+    }
+
+    let lines: Vec<String> = source
+        .lines()
+        .map(|line| {
+            let (qualifiers, rest) = layout(line);
+
+            if let Some(direction) = varying(rest).filter(|_| qualifiers.is_none()) {
+                locations[direction] += 1;
+                return format!("layout(location = {}) {rest}", locations[direction] - 1);
+            }
+
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            if words.first() == Some(&"buffer") {
+                return in_set(STORAGE_SET, qualifiers, rest);
+            }
+
+            if words.contains(&"uniform") && words.iter().any(|word| word.starts_with("image")) {
+                return in_set(IMAGE_SET, qualifiers, rest);
+            }
+
+            let Some((ty, name)) = loose_uniform(rest) else {
+                return line.to_string();
+            };
+
+            if let Some(kind) = ty.strip_prefix("sampler").filter(|_| !name.contains('[')) {
+                let texture = format!(
+                    "uniform texture{} {name}_texture;",
+                    kind.trim_end_matches("Shadow")
+                );
+                let sampler = match kind.ends_with("Shadow") {
+                    true => format!("uniform samplerShadow {name}_sampler;"),
+                    false => format!("uniform sampler {name}_sampler;"),
+                };
+                // Samplers without a binding read unit 0
+                let qualifiers = qualifiers
+                    .filter(|_| qualifier(qualifiers, "binding").is_some())
+                    .or(Some("binding = 0"));
+
+                aliases.push((
+                    name.to_string(),
+                    format!("{ty}({name}_texture, {name}_sampler)"),
+                ));
+                return format!(
+                    "{} {}",
+                    in_set(TEXTURE_SET, qualifiers, &texture),
+                    in_set(SAMPLER_SET, qualifiers, &sampler)
+                );
+            }
+
+            blocks += 1;
+            format!(
+                "layout(std140, set = {LOOSE_UNIFORM_SET}, binding = {blocks}) \
+                 uniform Uniform{blocks} {{ {ty} {name}; }};"
+            )
+        })
+        .collect();
+
+    (lines.join("\n"), aliases)
+}
+
+/// `statement` with `qualifiers` and a descriptor set
+fn in_set(set: u32, qualifiers: Option<&str>, statement: &str) -> String {
+    match qualifiers {
+        Some(qualifiers) => format!("layout(set = {set}, {qualifiers}) {statement}"),
+        None => format!("layout(set = {set}) {statement}"),
+    }
+}
+
+/// Splits a leading `layout(...)` off the statement on `line`, returning its qualifiers
+fn layout(line: &str) -> (Option<&str>, &str) {
+    let line = line.trim();
+    let split = line
+        .strip_prefix("layout")
+        .and_then(|layout| layout.trim_start().strip_prefix('('))
+        .and_then(|layout| layout.split_once(')'));
+
+    match split {
+        Some((qualifiers, rest)) => (Some(qualifiers), rest.trim_start()),
+        None => (None, line),
+    }
+}
+
+/// The value of a `key = value` layout qualifier
+fn qualifier(qualifiers: Option<&str>, key: &str) -> Option<u32> {
+    qualifiers?.split(',').find_map(|qualifier| {
+        let (name, value) = qualifier.split_once('=')?;
+        (name.trim() == key).then(|| value.trim().parse().ok())?
+    })
+}
+
+/// 0 for an `in` and 1 for an `out` declaration, possibly with an interpolation qualifier
+fn varying(statement: &str) -> Option<usize> {
+    let mut words = statement.split_whitespace();
+    let mut word = words.next()?;
+    if matches!(word, "flat" | "smooth" | "noperspective") {
+        word = words.next()?;
+    }
+
+    match word {
+        "in" => Some(0),
+        "out" => Some(1),
+        _ => None,
+    }
+}
+
+/// `(type, name)` of a `uniform` declared outside a block, alone on its line
+fn loose_uniform(statement: &str) -> Option<(&str, &str)> {
+    let declaration = statement.strip_prefix("uniform")?.strip_suffix(';')?;
+    match declaration.split_whitespace().collect::<Vec<_>>()[..] {
+        [ty, name] => Some((ty, name)),
+        _ => None,
+    }
+}
+
+struct SharedBlock {
+    name: String,
+    line: usize,
+    /// The whole declaration with comments removed and whitespace collapsed
+    declaration: String,
+}
+
+/// Finds `uniform` and `buffer` interface blocks, which have to be declared identically in
+/// every stage that shares them
+fn shared_blocks(source: &str) -> Vec<SharedBlock> {
+    let source = strip_comments(source);
+    let mut blocks = Vec::new();
+    let mut statement_start = 0;
+    let mut depth = 0;
+
+    for (offset, char) in source.char_indices() {
+        match char {
+            '{' if depth == 0 => {
+                depth = 1;
+                let head: Vec<&str> = source[statement_start..offset].split_whitespace().collect();
+
+                let is_block = head.len() >= 2
+                    && matches!(head[head.len() - 2], "uniform" | "buffer")
+                    && !head[head.len() - 1].contains('(');
+
+                if is_block {
+                    let end = block_end(&source, offset);
+                    let declaration = source[statement_start..end]
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+
+                    let leading = source[statement_start..].len()
+                        - source[statement_start..].trim_start().len();
+
+                    blocks.push(SharedBlock {
+                        name: head[head.len() - 1].to_string(),
+                        line: source[..statement_start + leading].matches('\n').count() + 1,
+                        declaration,
+                    });
+                }
+            }
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    statement_start = offset + 1;
+                }
+            }
+            ';' if depth == 0 => statement_start = offset + 1,
+            '\n' if depth == 0
+                && source[statement_start..offset]
+                    .trim_start()
+                    .starts_with('#') =>
+            {
+                statement_start = offset + 1;
+            }
+            _ => (),
+        }
+    }
+
+    blocks
+}
+
+/// Offset just past the `;` that ends the block whose `{` is at `open`
+fn block_end(source: &str, open: usize) -> usize {
+    let mut depth = 0;
+
+    for (offset, char) in source[open..].char_indices() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ';' if depth == 0 => return open + offset + 1,
+            _ => (),
+        }
+    }
+
+    source.len()
+}
+
+/// Replaces comments with spaces, keeping newlines so line numbers stay correct
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match (char, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&next| next != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        stripped.push('\n');
+                    }
+                    if last == '*' && next == '/' {
+                        break;
+                    }
+                    last = next;
+                }
+                stripped.push(' ');
+            }
+            _ => stripped.push(char),
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line `line` of `source`, counting from 1 like the diagnostics
+    fn line(source: &str, line: usize) -> &str {
+        source.lines().nth(line - 1).unwrap().trim()
+    }
+
+    #[test]
+    fn loose_uniforms_become_one_block_each() {
+        let (source, _) =
+            vulkan_glsl("#version 450 core\nuniform mat4 model;\nuniform float time;\n");
+
+        assert_eq!(
+            line(&source, 2),
+            "layout(std140, set = 2, binding = 1) uniform Uniform1 { mat4 model; };"
+        );
+        assert_eq!(
+            line(&source, 3),
+            "layout(std140, set = 2, binding = 2) uniform Uniform2 { float time; };"
+        );
+    }
+
+    #[test]
+    fn shadow_samplers_split_into_a_texture_and_a_comparison_sampler() {
+        let (source, aliases) =
+            vulkan_glsl("layout(binding = 3) uniform sampler2DShadow shadowMap;\n");
+
+        assert_eq!(
+            line(&source, 1),
+            "layout(set = 4, binding = 3) uniform texture2D shadowMap_texture; \
+             layout(set = 1, binding = 3) uniform samplerShadow shadowMap_sampler;"
+        );
+        assert!(aliases.contains(&(
+            "shadowMap".to_string(),
+            "sampler2DShadow(shadowMap_texture, shadowMap_sampler)".to_string()
+        )));
+    }
+
+    #[test]
+    fn varyings_without_a_location_take_the_next_free_one() {
+        let (source, _) = vulkan_glsl(
+            "layout(location = 0) in vec3 aPos;\n\
+             layout(location = 2) in vec2 aTexCoord;\n\
+             in vec3 aNormal;\n\
+             flat out int index;\n\
+             out vec2 TexCoord;\n",
+        );
+
+        assert_eq!(line(&source, 1), "layout(location = 0) in vec3 aPos;");
+        assert_eq!(line(&source, 3), "layout(location = 3) in vec3 aNormal;");
+        assert_eq!(line(&source, 4), "layout(location = 0) flat out int index;");
+        assert_eq!(line(&source, 5), "layout(location = 1) out vec2 TexCoord;");
+    }
+
+    #[test]
+    fn block_comments_keep_the_line_numbers() {
+        let source = "/* one\n   two\n   three */ uniform float a;\n// four\nuniform float b;\n";
+
+        let stripped = strip_comments(source);
+        assert_eq!(stripped.lines().count(), source.lines().count());
+        assert_eq!(line(&stripped, 3), "uniform float a;");
+        assert_eq!(line(&stripped, 5), "uniform float b;");
+
+        let (rewritten, _) = vulkan_glsl(source);
+        assert!(line(&rewritten, 5).contains("{ float b; }"));
+    }
+
+    #[test]
+    fn errors_after_a_block_comment_report_the_line_of_the_file() {
+        let source =
+            "#version 450 core\n/*\n * A comment\n */\nvoid main() {\n    undeclared = 1.0;\n}\n";
+
+        let diagnostics = check(source, ShaderStage::Fragment, &Defines::new());
+        assert_eq!(diagnostics.first().map(|(line, _)| *line), Some(6));
+    }
+
+    #[test]
+    fn mismatched_blocks_are_reported_against_the_majority() {
+        let matrix_block = "layout(std140, binding = 0) uniform MatrixBlock {\n    mat4 projection;\n    mat4 view;\n    mat4 model;\n};\n";
+        let swapped = "#version 450 core\n// Light\nlayout(std140, binding = 0) uniform MatrixBlock {\n    mat4 view;\n    mat4 projection;\n    mat4 model;\n};\n";
+
+        let files = [
+            (
+                "a.vert".to_string(),
+                format!("#version 450 core\n{matrix_block}"),
+            ),
+            ("b.vert".to_string(), swapped.to_string()),
+            (
+                "c.vert".to_string(),
+                format!("#version 450 core\n\n{matrix_block}"),
+            ),
+        ];
+
+        let mismatches = block_mismatches(&files);
+        assert_eq!(mismatches.len(), 1);
+        assert!(
+            mismatches[0].starts_with(
+                "b.vert:3: error: `MatrixBlock` differs from the declaration at a.vert:2\n"
+            ),
+            "{}",
+            mismatches[0]
+        );
+
+        let blocks = shared_blocks(&files[2].1);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            (blocks[0].name.as_str(), blocks[0].line),
+            ("MatrixBlock", 3)
+        );
+    }
+}
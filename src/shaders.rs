@@ -1,51 +1,174 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{c_char, CString};
-use std::{fs, ptr};
+use std::ptr;
 
 use opengl::gl;
 
+use crate::assets;
+
 use self::sealed::{UniformValue, UniformValueTranspose};
 
+pub use self::binary_cache::ProgramBinaryCache;
+pub(crate) use self::binary_cache::{entries, evict, touch, write_entry, Fnv1a, DEFAULT_MAX_SIZE};
+#[cfg(feature = "check-shaders")]
+pub use self::permutations::PERMUTATIONS;
+pub use self::permutations::{flags, ShaderCache};
+pub use self::preprocess::Defines;
+pub use self::reflection::ProgramReflection;
+
+mod binary_cache;
+mod permutations;
+pub mod pipeline;
+mod preprocess;
+mod reflection;
+pub mod spirv;
+
+pub mod types {
+    pub use super::reflection::{glsl_type, type_name};
+}
+
 pub struct Shader {
     pub id: u32,
+    reflection: ProgramReflection,
+    locations: HashMap<String, i32>,
+    missing_uniforms: RefCell<HashSet<String>>,
 }
 
 #[allow(dead_code)]
 impl Shader {
     pub fn new(v_shader_file: &str, f_shader_file: &str) -> Shader {
-        let v_shader = fs::read_to_string(v_shader_file);
-        let f_shader = fs::read_to_string(f_shader_file);
+        Shader::with_defines(v_shader_file, f_shader_file, &Defines::new())
+    }
 
-        let Ok(v_shader) = v_shader else {
-            panic!("Vertex Shader Error: {v_shader:?}")
-        };
+    pub fn with_defines(v_shader_file: &str, f_shader_file: &str, defines: &Defines) -> Shader {
+        Shader::from_files(
+            &[
+                (gl::VERTEX_SHADER, v_shader_file),
+                (gl::FRAGMENT_SHADER, f_shader_file),
+            ],
+            defines,
+            None,
+        )
+    }
+
+    pub fn new_compute(c_shader_file: &str) -> Shader {
+        Shader::compute_with_defines(c_shader_file, &Defines::new())
+    }
+
+    pub fn compute_with_defines(c_shader_file: &str, defines: &Defines) -> Shader {
+        Shader::from_files(&[(gl::COMPUTE_SHADER, c_shader_file)], defines, None)
+    }
+
+    pub fn from_files(
+        files: &[(gl::types::GLenum, &str)],
+        defines: &Defines,
+        binary_cache: Option<&ProgramBinaryCache>,
+    ) -> Shader {
+        Shader::from_sources_cached(&Shader::read_sources(files), defines, binary_cache)
+    }
+
+    /// Reads every stage's source, panicking with the stage name if a file can't be read
+    pub fn read_sources(files: &[(gl::types::GLenum, &str)]) -> Vec<(gl::types::GLenum, String)> {
+        files
+            .iter()
+            .map(|&(stage, file)| {
+                let source = assets::read_to_string(file);
+
+                let Ok(source) = source else {
+                    panic!("{} Shader Error: {source:?}", stage_name(stage))
+                };
+
+                (stage, source)
+            })
+            .collect()
+    }
+
+    pub fn from_sources(stages: &[(gl::types::GLenum, String)], defines: &Defines) -> Shader {
+        Shader::from_sources_cached(stages, defines, None)
+    }
 
-        let Ok(f_shader) = f_shader else {
-            panic!("Fragment Shader Error: {f_shader:?}")
+    pub fn from_sources_cached(
+        stages: &[(gl::types::GLenum, String)],
+        defines: &Defines,
+        binary_cache: Option<&ProgramBinaryCache>,
+    ) -> Shader {
+        let stages: Vec<_> = stages
+            .iter()
+            .map(|(stage, source)| (*stage, defines.inject(source)))
+            .collect();
+
+        let Some(binary_cache) = binary_cache else {
+            return Shader::from_program(Shader::link(&stages, false));
         };
 
-        let v_shader_code = CString::new(v_shader).unwrap();
-        let f_shader_code = CString::new(f_shader).unwrap();
-        unsafe {
-            let vertex_shader: u32 = gl::CreateShader(gl::VERTEX_SHADER);
-            Shader::compile_shader(vertex_shader, 1, v_shader_code);
+        let key = binary_cache.key(&stages);
+        if let Some(id) = binary_cache.load(key) {
+            return Shader::from_program(id);
+        }
+
+        let id = Shader::link(&stages, true);
+        if Shader::get_link_success(id) {
+            binary_cache.store(key, id);
+        }
 
-            let fragment_shader: u32 = gl::CreateShader(gl::FRAGMENT_SHADER);
-            Shader::compile_shader(fragment_shader, 1, f_shader_code);
+        Shader::from_program(id)
+    }
 
+    fn link(stages: &[(gl::types::GLenum, String)], retrievable: bool) -> u32 {
+        unsafe {
             let id = gl::CreateProgram();
-            gl::AttachShader(id, vertex_shader);
-            gl::AttachShader(id, fragment_shader);
+
+            if retrievable {
+                gl::ProgramParameteri(id, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
+            }
+
+            let shaders: Vec<u32> = stages
+                .iter()
+                .map(|(stage, source)| {
+                    let shader_code = CString::new(source.as_str()).unwrap();
+                    let shader = gl::CreateShader(*stage);
+                    Shader::compile_shader(shader, 1, shader_code);
+                    gl::AttachShader(id, shader);
+                    shader
+                })
+                .collect();
+
             gl::LinkProgram(id);
 
-            if !Shader::get_success(id) {
-                let log = Shader::get_info_log(id);
-                println!("{log:?}");
+            for shader in shaders {
+                gl::DeleteShader(shader);
             }
 
-            gl::DeleteShader(fragment_shader);
-            gl::DeleteShader(vertex_shader);
+            id
+        }
+    }
+
+    fn from_program(id: u32) -> Shader {
+        if !Shader::get_link_success(id) {
+            let log = unsafe { Shader::get_program_info_log(id) };
+            panic!("Program Link Error: {log:?}");
+        }
+
+        let reflection = ProgramReflection::new(id);
+
+        let mut locations = HashMap::new();
+        for uniform in &reflection.uniforms {
+            // Arrays are reported as `name[0]`, but should also be reachable as `name`
+            if let Some(base) = uniform.name.strip_suffix("[0]") {
+                locations.insert(base.to_string(), uniform.location);
+            }
+
+            let elements =
+                reflection::array_elements(id, &uniform.name, uniform.location, uniform.array_size);
+            locations.extend(elements);
+        }
 
-            Shader { id }
+        Shader {
+            id,
+            reflection,
+            locations,
+            missing_uniforms: RefCell::new(HashSet::new()),
         }
     }
 
@@ -86,6 +209,35 @@ impl Shader {
         CString::from_vec_unchecked(buf)
     }
 
+    fn get_link_success(program: u32) -> bool {
+        unsafe {
+            let mut success = gl::FALSE as i32;
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
+            success != gl::FALSE as i32
+        }
+    }
+
+    unsafe fn get_program_info_log(program: u32) -> CString {
+        let mut buf_cap = 0;
+        gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut buf_cap);
+        let mut buf = Vec::with_capacity(buf_cap as usize);
+        let mut buf_len = 0;
+        gl::GetProgramInfoLog(
+            program,
+            buf_cap,
+            &mut buf_len,
+            buf.as_mut_ptr() as *mut c_char,
+        );
+
+        buf.set_len(buf_len as usize);
+
+        CString::from_vec_unchecked(buf)
+    }
+
+    pub fn reflection(&self) -> &ProgramReflection {
+        &self.reflection
+    }
+
     pub fn use_program(&self) {
         unsafe {
             gl::UseProgram(self.id);
@@ -96,14 +248,16 @@ impl Shader {
         unsafe { gl::GetUniformBlockIndex(self.id, ubo_name.as_ptr()) }
     }
 
-    pub fn uniform_block_binding(&self, ubo_index: u32) {
+    pub fn uniform_block_binding(&self, ubo_index: u32, binding: u32) {
         unsafe {
-            gl::UniformBlockBinding(self.id, ubo_index, 0);
+            gl::UniformBlockBinding(self.id, ubo_index, binding);
         }
     }
 
     pub fn set_uniform(&self, name: &str, value: impl UniformValue) {
-        value.set(self, name);
+        if let Some(location) = self.uniform_location(name) {
+            value.set(self.id, location);
+        }
     }
 
     pub fn set_uniform_transpose(
@@ -112,70 +266,261 @@ impl Shader {
         value: impl UniformValueTranspose,
         transpose: bool,
     ) {
-        value.set(self, name, transpose);
+        if let Some(location) = self.uniform_location(name) {
+            value.set(self.id, location, transpose);
+        }
     }
 
-    fn uniform_location(&self, name: &str) -> i32 {
-        unsafe {
-            let name = CString::new(name).unwrap();
-            gl::GetUniformLocation(self.id, name.as_ptr())
+    fn uniform_location(&self, name: &str) -> Option<i32> {
+        let location = self.locations.get(name).copied();
+
+        if location.is_none() && self.note_missing_uniform(name) {
+            println!(
+                "Warning: uniform `{name}` is not active in program {}",
+                self.id
+            );
         }
+
+        location
+    }
+
+    /// `true` only the first time `name` is reported, so every inactive uniform is warned about
+    /// once instead of every frame
+    pub(crate) fn note_missing_uniform(&self, name: &str) -> bool {
+        self.missing_uniforms.borrow_mut().insert(name.to_string())
+    }
+}
+
+fn stage_name(stage: gl::types::GLenum) -> &'static str {
+    match stage {
+        gl::VERTEX_SHADER => "Vertex",
+        gl::FRAGMENT_SHADER => "Fragment",
+        gl::GEOMETRY_SHADER => "Geometry",
+        gl::TESS_CONTROL_SHADER => "Tessellation Control",
+        gl::TESS_EVALUATION_SHADER => "Tessellation Evaluation",
+        gl::COMPUTE_SHADER => "Compute",
+        _ => "Unknown",
     }
 }
 
 mod sealed {
-    use nalgebra_glm::{TMat, Vec3};
+    use nalgebra_glm::{TMat, TVec};
     use opengl::gl;
 
-    use super::Shader;
+    /// A type with a matching `ProgramUniform*v` call, uploaded straight from memory
+    pub trait UniformElement: Sized {
+        unsafe fn upload(
+            program: u32,
+            location: i32,
+            count: i32,
+            transpose: bool,
+            ptr: *const Self,
+        );
+    }
 
-    pub trait UniformValue: Sized {
-        fn set(self, shader: &Shader, name: &str);
+    /// Matrices are the only elements where `transpose` means anything
+    pub trait MatrixElement: UniformElement {}
+
+    macro_rules! vector_element {
+        ($($ty:ty => $scalar:ty, $func:ident;)*) => {$(
+            impl UniformElement for $ty {
+                unsafe fn upload(
+                    program: u32,
+                    location: i32,
+                    count: i32,
+                    _transpose: bool,
+                    ptr: *const Self,
+                ) {
+                    gl::$func(program, location, count, ptr as *const $scalar)
+                }
+            }
+
+            impl UniformValue for $ty {
+                fn set(self, program: u32, location: i32) {
+                    UniformValue::set(&self, program, location);
+                }
+            }
+        )*};
     }
 
-    impl UniformValue for f32 {
-        fn set(self, shader: &Shader, name: &str) {
-            unsafe {
-                let location = shader.uniform_location(name);
-                gl::Uniform1f(location, self)
+    macro_rules! matrix_element {
+        ($($ty:ty => $scalar:ty, $func:ident;)*) => {$(
+            impl UniformElement for $ty {
+                unsafe fn upload(
+                    program: u32,
+                    location: i32,
+                    count: i32,
+                    transpose: bool,
+                    ptr: *const Self,
+                ) {
+                    gl::$func(program, location, count, transpose as u8, ptr as *const $scalar)
+                }
+            }
+
+            impl MatrixElement for $ty {}
+
+            impl UniformValue for $ty {
+                fn set(self, program: u32, location: i32) {
+                    UniformValue::set(&self, program, location);
+                }
+            }
+
+            impl UniformValueTranspose for $ty {
+                fn set(self, program: u32, location: i32, transpose: bool) {
+                    UniformValueTranspose::set(&self, program, location, transpose);
+                }
             }
+        )*};
+    }
+
+    vector_element! {
+        f32 => f32, ProgramUniform1fv;
+        TVec<f32, 2> => f32, ProgramUniform2fv;
+        TVec<f32, 3> => f32, ProgramUniform3fv;
+        TVec<f32, 4> => f32, ProgramUniform4fv;
+        i32 => i32, ProgramUniform1iv;
+        TVec<i32, 2> => i32, ProgramUniform2iv;
+        TVec<i32, 3> => i32, ProgramUniform3iv;
+        TVec<i32, 4> => i32, ProgramUniform4iv;
+        u32 => u32, ProgramUniform1uiv;
+        TVec<u32, 2> => u32, ProgramUniform2uiv;
+        TVec<u32, 3> => u32, ProgramUniform3uiv;
+        TVec<u32, 4> => u32, ProgramUniform4uiv;
+        f64 => f64, ProgramUniform1dv;
+        TVec<f64, 2> => f64, ProgramUniform2dv;
+        TVec<f64, 3> => f64, ProgramUniform3dv;
+        TVec<f64, 4> => f64, ProgramUniform4dv;
+    }
+
+    // nalgebra matrices are `TMat<T, ROWS, COLUMNS>` while GLSL names them `matCOLUMNSxROWS`,
+    // both are column major
+    matrix_element! {
+        TMat<f32, 2, 2> => f32, ProgramUniformMatrix2fv;
+        TMat<f32, 3, 3> => f32, ProgramUniformMatrix3fv;
+        TMat<f32, 4, 4> => f32, ProgramUniformMatrix4fv;
+        TMat<f32, 3, 2> => f32, ProgramUniformMatrix2x3fv;
+        TMat<f32, 4, 2> => f32, ProgramUniformMatrix2x4fv;
+        TMat<f32, 2, 3> => f32, ProgramUniformMatrix3x2fv;
+        TMat<f32, 4, 3> => f32, ProgramUniformMatrix3x4fv;
+        TMat<f32, 2, 4> => f32, ProgramUniformMatrix4x2fv;
+        TMat<f32, 3, 4> => f32, ProgramUniformMatrix4x3fv;
+        TMat<f64, 2, 2> => f64, ProgramUniformMatrix2dv;
+        TMat<f64, 3, 3> => f64, ProgramUniformMatrix3dv;
+        TMat<f64, 4, 4> => f64, ProgramUniformMatrix4dv;
+        TMat<f64, 3, 2> => f64, ProgramUniformMatrix2x3dv;
+        TMat<f64, 4, 2> => f64, ProgramUniformMatrix2x4dv;
+        TMat<f64, 2, 3> => f64, ProgramUniformMatrix3x2dv;
+        TMat<f64, 4, 3> => f64, ProgramUniformMatrix3x4dv;
+        TMat<f64, 2, 4> => f64, ProgramUniformMatrix4x2dv;
+        TMat<f64, 3, 4> => f64, ProgramUniformMatrix4x3dv;
+    }
+
+    pub trait UniformValue: Sized {
+        fn set(self, program: u32, location: i32);
+    }
+
+    impl<T: UniformElement> UniformValue for &T {
+        fn set(self, program: u32, location: i32) {
+            unsafe { T::upload(program, location, 1, false, self) }
         }
     }
 
-    impl UniformValue for i32 {
-        fn set(self, shader: &Shader, name: &str) {
-            unsafe {
-                let location = shader.uniform_location(name);
-                gl::Uniform1i(location, self)
-            }
+    impl<T: UniformElement> UniformValue for &[T] {
+        fn set(self, program: u32, location: i32) {
+            unsafe { T::upload(program, location, self.len() as i32, false, self.as_ptr()) }
+        }
+    }
+
+    impl<T: UniformElement, const N: usize> UniformValue for [T; N] {
+        fn set(self, program: u32, location: i32) {
+            UniformValue::set(self.as_slice(), program, location);
         }
     }
 
+    impl<T: UniformElement, const N: usize> UniformValue for &[T; N] {
+        fn set(self, program: u32, location: i32) {
+            UniformValue::set(self.as_slice(), program, location);
+        }
+    }
+
+    // GLSL booleans are set through the integer calls, so they get widened first
     impl UniformValue for bool {
-        fn set(self, shader: &Shader, name: &str) {
-            (self as i32).set(shader, name);
+        fn set(self, program: u32, location: i32) {
+            (self as i32).set(program, location);
         }
     }
 
-    impl UniformValue for &Vec3 {
-        fn set(self, shader: &Shader, name: &str) {
-            unsafe {
-                let location = shader.uniform_location(name);
-                gl::Uniform3fv(location, self.len() as i32, self.as_ptr())
-            }
+    impl<const N: usize> UniformValue for TVec<bool, N>
+    where
+        TVec<i32, N>: UniformElement,
+    {
+        fn set(self, program: u32, location: i32) {
+            self.map(|value| value as i32).set(program, location);
+        }
+    }
+
+    impl<const N: usize> UniformValue for &TVec<bool, N>
+    where
+        TVec<i32, N>: UniformElement,
+    {
+        fn set(self, program: u32, location: i32) {
+            self.map(|value| value as i32).set(program, location);
+        }
+    }
+
+    impl UniformValue for &[bool] {
+        fn set(self, program: u32, location: i32) {
+            let values: Vec<i32> = self.iter().map(|&value| value as i32).collect();
+            values.as_slice().set(program, location);
+        }
+    }
+
+    impl<const N: usize> UniformValue for &[TVec<bool, N>]
+    where
+        TVec<i32, N>: UniformElement,
+    {
+        fn set(self, program: u32, location: i32) {
+            let values: Vec<TVec<i32, N>> = self
+                .iter()
+                .map(|value| value.map(|value| value as i32))
+                .collect();
+            values.as_slice().set(program, location);
         }
     }
 
     pub trait UniformValueTranspose: Sized {
-        fn set(self, shader: &Shader, name: &str, transpose: bool);
+        fn set(self, program: u32, location: i32, transpose: bool);
+    }
+
+    impl<T: MatrixElement> UniformValueTranspose for &T {
+        fn set(self, program: u32, location: i32, transpose: bool) {
+            unsafe { T::upload(program, location, 1, transpose, self) }
+        }
     }
 
-    impl<const R: usize, const C: usize> UniformValueTranspose for TMat<f32, R, C> {
-        fn set(self, shader: &Shader, name: &str, transpose: bool) {
+    impl<T: MatrixElement> UniformValueTranspose for &[T] {
+        fn set(self, program: u32, location: i32, transpose: bool) {
             unsafe {
-                let location = shader.uniform_location(name);
-                gl::UniformMatrix4fv(location, self.len() as i32, transpose as u8, self.as_ptr());
+                T::upload(
+                    program,
+                    location,
+                    self.len() as i32,
+                    transpose,
+                    self.as_ptr(),
+                )
             }
         }
     }
+
+    impl<T: MatrixElement, const N: usize> UniformValueTranspose for [T; N] {
+        fn set(self, program: u32, location: i32, transpose: bool) {
+            UniformValueTranspose::set(self.as_slice(), program, location, transpose);
+        }
+    }
+
+    impl<T: MatrixElement, const N: usize> UniformValueTranspose for &[T; N] {
+        fn set(self, program: u32, location: i32, transpose: bool) {
+            UniformValueTranspose::set(self.as_slice(), program, location, transpose);
+        }
+    }
 }
@@ -0,0 +1,298 @@
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use opengl::gl;
+
+use super::Shader;
+
+/// Size cap of the on-disk caches when none is given
+pub(crate) const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Linked program binaries stored as `<key>.bin`, a little endian `u32` binary format followed
+/// by the driver's blob
+pub struct ProgramBinaryCache {
+    dir: PathBuf,
+    max_size: u64,
+    driver: String,
+    enabled: bool,
+}
+
+#[allow(dead_code)]
+impl ProgramBinaryCache {
+    pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> ProgramBinaryCache {
+        let dir = dir.into();
+
+        let mut formats = 0;
+        unsafe {
+            gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+        }
+
+        let mut enabled = formats > 0;
+        if !enabled {
+            println!("Program binaries are not supported by this driver, caching disabled");
+        } else if let Err(error) = fs::create_dir_all(&dir) {
+            println!("Program binary cache {dir:?} is not usable: {error}");
+            enabled = false;
+        }
+
+        ProgramBinaryCache {
+            dir,
+            max_size,
+            driver: driver_string(),
+            enabled,
+        }
+    }
+
+    /// Uses `$SHADER_CACHE_DIR` or the system temp directory, capped at 64 MiB
+    pub fn from_env() -> ProgramBinaryCache {
+        let dir = std::env::var_os("SHADER_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                std::env::temp_dir()
+                    .join("opengl-stuffs-uwu")
+                    .join("shaders")
+            });
+
+        ProgramBinaryCache::new(dir, DEFAULT_MAX_SIZE)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// `stages` are the preprocessed sources, so the defines are already part of them
+    pub fn key(&self, stages: &[(gl::types::GLenum, String)]) -> u64 {
+        let mut hash = Fnv1a::new();
+        hash.write(self.driver.as_bytes());
+
+        for (stage, source) in stages {
+            hash.write(&stage.to_le_bytes());
+            hash.write(&(source.len() as u64).to_le_bytes());
+            hash.write(source.as_bytes());
+        }
+
+        hash.finish()
+    }
+
+    /// Returns a linked program, or `None` when there is no entry or the driver rejects it
+    pub fn load(&self, key: u64) -> Option<u32> {
+        if !self.enabled {
+            return None;
+        }
+
+        let path = self.path(key);
+        let data = fs::read(&path).ok()?;
+
+        if data.len() < 4 {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+
+        let (format, binary) = data.split_at(4);
+        let format = u32::from_le_bytes(format.try_into().unwrap());
+
+        let id = unsafe {
+            let id = gl::CreateProgram();
+            gl::ProgramBinary(id, format, binary.as_ptr() as *const _, binary.len() as i32);
+            id
+        };
+
+        if !Shader::get_link_success(id) {
+            // Driver updates invalidate binaries, so this entry will never load again
+            unsafe {
+                gl::DeleteProgram(id);
+            }
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+
+        touch(&path);
+
+        Some(id)
+    }
+
+    pub fn store(&self, key: u64, program: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut length = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        }
+
+        if length <= 0 {
+            return;
+        }
+
+        let mut data = vec![0u8; 4 + length as usize];
+        let mut format = 0;
+        let mut written = 0;
+        unsafe {
+            gl::GetProgramBinary(
+                program,
+                length,
+                &mut written,
+                &mut format,
+                data[4..].as_mut_ptr() as *mut _,
+            );
+        }
+
+        data.truncate(4 + written as usize);
+        data[..4].copy_from_slice(&format.to_le_bytes());
+
+        if let Err(error) = self.write(key, &data) {
+            println!(
+                "Failed to write program binary {:?}: {error}",
+                self.path(key)
+            );
+        }
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        for (path, _, _) in entries(&self.dir, "bin")? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, key: u64, data: &[u8]) -> io::Result<()> {
+        write_entry(&self.path(key), data)?;
+        evict(&self.dir, "bin", self.max_size)
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.bin"))
+    }
+}
+
+/// Written to a temporary file first so a crash never leaves a truncated entry behind
+pub(crate) fn write_entry(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, data)?;
+    fs::rename(&temp, path)
+}
+
+/// Bumps the modification time so eviction drops the least recently used entries first
+pub(crate) fn touch(path: &Path) {
+    let _ = fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+}
+
+/// Removes the least recently used `*.<extension>` entries of `dir` until the rest fit in
+/// `max_size`
+pub(crate) fn evict(dir: &Path, extension: &str, max_size: u64) -> io::Result<()> {
+    let mut entries = entries(dir, extension)?;
+    let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+
+    entries.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, size, _) in entries {
+        if total <= max_size {
+            break;
+        }
+
+        fs::remove_file(path)?;
+        total -= size;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn entries(dir: &Path, extension: &str) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path.extension() != Some(extension.as_ref()) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        entries.push((path, metadata.len(), modified));
+    }
+
+    Ok(entries)
+}
+
+fn driver_string() -> String {
+    [gl::VENDOR, gl::RENDERER, gl::VERSION]
+        .map(|name| unsafe {
+            let string = gl::GetString(name);
+            if string.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(string as *const _)
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        })
+        .join("\n")
+}
+
+/// `DefaultHasher` isn't guaranteed to be stable between Rust releases, which would silently
+/// invalidate the whole cache, so keys use FNV-1a instead
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn eviction_drops_the_least_recently_used_entries() {
+        let dir = std::env::temp_dir().join(format!("evict-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let now = SystemTime::now();
+        for (index, name) in ["a", "b", "c"].into_iter().enumerate() {
+            let path = dir.join(name).with_extension("bin");
+            write_entry(&path, &[0; 10]).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(now - Duration::from_secs(10 - index as u64)))
+                .unwrap();
+        }
+        fs::write(dir.join("other.ibl"), [0; 100]).unwrap();
+
+        touch(&dir.join("a.bin"));
+        evict(&dir, "bin", 20).unwrap();
+
+        let mut left: Vec<_> = entries(&dir, "bin")
+            .unwrap()
+            .into_iter()
+            .map(|(path, _, _)| path.file_name().unwrap().to_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(left, ["a.bin", "c.bin"]);
+    }
+}
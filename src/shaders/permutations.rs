@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::ops::Index;
+
+use opengl::gl;
+
+use super::{Defines, ProgramBinaryCache, Shader};
+
+/// The flags the app selects shader variants with
+pub mod flags {
+    pub const CLUSTERED: &str = "CLUSTERED";
+    pub const DEFERRED: &str = "DEFERRED";
+    pub const POINT_SHADOW: &str = "POINT_SHADOW";
+    pub const EQUIRECT_TO_CUBE: &str = "EQUIRECT_TO_CUBE";
+    pub const IRRADIANCE: &str = "IRRADIANCE";
+    pub const PREFILTER: &str = "PREFILTER";
+}
+
+/// The flags each file is compiled with besides none. `check-shaders` validates every one of
+/// them, so a new variant goes in here along with its flag
+#[cfg(feature = "check-shaders")]
+pub const PERMUTATIONS: &[(&str, &[&str])] = &[
+    ("pbr.frag", &[flags::CLUSTERED, flags::DEFERRED]),
+    ("shadow_depth.frag", &[flags::POINT_SHADOW]),
+    ("tile.frag", &[flags::DEFERRED]),
+    (
+        "ibl.frag",
+        &[flags::EQUIRECT_TO_CUBE, flags::IRRADIANCE, flags::PREFILTER],
+    ),
+];
+
+/// Selects a compiled variant out of a `ShaderCache` without recompiling or hashing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(usize);
+
+/// The preprocessed source of every stage, so an edited file compiles again instead of hitting
+/// the program built from its old contents
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderKey {
+    sources: Vec<(gl::types::GLenum, String)>,
+}
+
+pub struct ShaderCache {
+    programs: HashMap<ShaderKey, ShaderHandle>,
+    shaders: Vec<Shader>,
+    binary_cache: Option<ProgramBinaryCache>,
+}
+
+#[allow(dead_code)]
+impl ShaderCache {
+    pub fn new() -> ShaderCache {
+        ShaderCache {
+            programs: HashMap::new(),
+            shaders: Vec::new(),
+            binary_cache: None,
+        }
+    }
+
+    /// Linked programs are also stored on disk and reused on the next launch
+    pub fn with_binary_cache(binary_cache: ProgramBinaryCache) -> ShaderCache {
+        ShaderCache {
+            binary_cache: Some(binary_cache),
+            ..ShaderCache::new()
+        }
+    }
+
+    pub fn get(
+        &mut self,
+        v_shader_file: &str,
+        f_shader_file: &str,
+        defines: &Defines,
+    ) -> ShaderHandle {
+        self.get_or_insert(
+            &[
+                (gl::VERTEX_SHADER, v_shader_file),
+                (gl::FRAGMENT_SHADER, f_shader_file),
+            ],
+            defines,
+        )
+    }
+
+    pub fn get_compute(&mut self, c_shader_file: &str, defines: &Defines) -> ShaderHandle {
+        self.get_or_insert(&[(gl::COMPUTE_SHADER, c_shader_file)], defines)
+    }
+
+    /// Compiles every combination of `defines` up front, so no variant compiles mid-frame
+    pub fn get_all(
+        &mut self,
+        v_shader_file: &str,
+        f_shader_file: &str,
+        defines: &[Defines],
+    ) -> Vec<ShaderHandle> {
+        defines
+            .iter()
+            .map(|defines| self.get(v_shader_file, f_shader_file, defines))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shaders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shaders.is_empty()
+    }
+
+    fn get_or_insert(
+        &mut self,
+        files: &[(gl::types::GLenum, &str)],
+        defines: &Defines,
+    ) -> ShaderHandle {
+        let stages = Shader::read_sources(files);
+        let key = ShaderKey {
+            sources: stages
+                .iter()
+                .map(|(stage, source)| (*stage, defines.inject(source)))
+                .collect(),
+        };
+
+        if let Some(&handle) = self.programs.get(&key) {
+            return handle;
+        }
+
+        let handle = ShaderHandle(self.shaders.len());
+        self.shaders.push(Shader::from_sources_cached(
+            &stages,
+            defines,
+            self.binary_cache.as_ref(),
+        ));
+        self.programs.insert(key, handle);
+
+        handle
+    }
+}
+
+impl Index<ShaderHandle> for ShaderCache {
+    type Output = Shader;
+
+    fn index(&self, handle: ShaderHandle) -> &Self::Output {
+        &self.shaders[handle.0]
+    }
+}
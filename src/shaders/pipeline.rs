@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::ffi::{c_char, CString};
+
+use opengl::gl;
+
+use crate::assets;
+
+use super::reflection::{type_name, InterfaceVariable};
+use super::{stage_name, Defines, Shader};
+
+/// A single stage program linked with `PROGRAM_SEPARABLE`, to be mixed with other stages in a
+/// `ProgramPipeline`
+pub struct SeparableProgram {
+    shader: Shader,
+    stage: gl::types::GLenum,
+}
+
+#[allow(dead_code)]
+impl SeparableProgram {
+    pub fn new(stage: gl::types::GLenum, shader_file: &str) -> SeparableProgram {
+        SeparableProgram::with_defines(stage, shader_file, &Defines::new())
+    }
+
+    pub fn with_defines(
+        stage: gl::types::GLenum,
+        shader_file: &str,
+        defines: &Defines,
+    ) -> SeparableProgram {
+        let source = assets::read_to_string(shader_file);
+
+        let Ok(source) = source else {
+            panic!("{} Shader Error: {source:?}", stage_name(stage))
+        };
+
+        SeparableProgram::from_source(stage, &source, defines)
+    }
+
+    pub fn from_source(
+        stage: gl::types::GLenum,
+        source: &str,
+        defines: &Defines,
+    ) -> SeparableProgram {
+        let shader_code = CString::new(defines.inject(source)).unwrap();
+
+        let shader = unsafe {
+            let id = gl::CreateProgram();
+            gl::ProgramParameteri(id, gl::PROGRAM_SEPARABLE, gl::TRUE as i32);
+
+            let shader = gl::CreateShader(stage);
+            Shader::compile_shader(shader, 1, shader_code);
+            gl::AttachShader(id, shader);
+            gl::LinkProgram(id);
+            gl::DeleteShader(shader);
+
+            Shader::from_program(id)
+        };
+
+        SeparableProgram { shader, stage }
+    }
+
+    pub fn shader(&self) -> &Shader {
+        &self.shader
+    }
+
+    pub fn stage(&self) -> gl::types::GLenum {
+        self.stage
+    }
+}
+
+pub struct ProgramPipeline {
+    id: u32,
+}
+
+#[allow(dead_code)]
+impl ProgramPipeline {
+    /// Panics if the outputs of a stage don't match the inputs of the next one
+    pub fn new(programs: &[&SeparableProgram]) -> ProgramPipeline {
+        let mut programs = programs.to_vec();
+        programs.sort_by_key(|program| stage_order(program.stage));
+
+        for pair in programs.windows(2) {
+            if pair[0].stage == pair[1].stage {
+                panic!("Pipeline has two {} stages", stage_name(pair[0].stage));
+            }
+            validate_interface(pair[0], pair[1]);
+        }
+
+        unsafe {
+            let mut id = 0;
+            gl::CreateProgramPipelines(1, &mut id);
+
+            for program in &programs {
+                gl::UseProgramStages(id, stage_bit(program.stage), program.shader.id);
+            }
+
+            gl::ValidateProgramPipeline(id);
+
+            let mut success = gl::FALSE as i32;
+            gl::GetProgramPipelineiv(id, gl::VALIDATE_STATUS, &mut success);
+            if success == gl::FALSE as i32 {
+                let log = ProgramPipeline::get_info_log(id);
+                panic!("Program Pipeline Error: {log:?}");
+            }
+
+            ProgramPipeline { id }
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            // A bound program takes precedence over the pipeline
+            gl::UseProgram(0);
+            gl::BindProgramPipeline(self.id);
+        }
+    }
+
+    unsafe fn get_info_log(id: u32) -> CString {
+        let mut buf_cap = 0;
+        gl::GetProgramPipelineiv(id, gl::INFO_LOG_LENGTH, &mut buf_cap);
+        let mut buf = Vec::with_capacity(buf_cap as usize);
+        let mut buf_len = 0;
+        gl::GetProgramPipelineInfoLog(id, buf_cap, &mut buf_len, buf.as_mut_ptr() as *mut c_char);
+
+        buf.set_len(buf_len as usize);
+
+        CString::from_vec_unchecked(buf)
+    }
+}
+
+impl Drop for ProgramPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgramPipelines(1, &self.id);
+        }
+    }
+}
+
+/// Reuses pipeline objects for stage combinations that were already validated
+pub struct PipelineCache {
+    pipelines: HashMap<Vec<u32>, ProgramPipeline>,
+}
+
+#[allow(dead_code)]
+impl PipelineCache {
+    pub fn new() -> PipelineCache {
+        PipelineCache {
+            pipelines: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, programs: &[&SeparableProgram]) -> &ProgramPipeline {
+        let mut key: Vec<u32> = programs.iter().map(|program| program.shader.id).collect();
+        key.sort_unstable();
+
+        self.pipelines
+            .entry(key)
+            .or_insert_with(|| ProgramPipeline::new(programs))
+    }
+
+    pub fn bind(&mut self, programs: &[&SeparableProgram]) {
+        self.get(programs).bind();
+    }
+}
+
+fn validate_interface(producer: &SeparableProgram, consumer: &SeparableProgram) {
+    let outputs = &producer.shader.reflection().outputs;
+
+    for input in &consumer.shader.reflection().inputs {
+        let Some(output) = find_matching(outputs, input) else {
+            panic!(
+                "{} input `{}` has no matching {} output",
+                stage_name(consumer.stage),
+                input.name,
+                stage_name(producer.stage)
+            );
+        };
+
+        assert!(
+            output.gl_type == input.gl_type && output.array_size == input.array_size,
+            "{} output `{}` is {}[{}], but {} input `{}` is {}[{}]",
+            stage_name(producer.stage),
+            output.name,
+            type_name(output.gl_type),
+            output.array_size,
+            stage_name(consumer.stage),
+            input.name,
+            type_name(input.gl_type),
+            input.array_size
+        );
+    }
+}
+
+/// Separable stages match by location when both sides declare one, otherwise by name
+fn find_matching<'a>(
+    outputs: &'a [InterfaceVariable],
+    input: &InterfaceVariable,
+) -> Option<&'a InterfaceVariable> {
+    outputs.iter().find(|output| {
+        if output.location != -1 && input.location != -1 {
+            output.location == input.location
+        } else {
+            output.name == input.name
+        }
+    })
+}
+
+fn stage_order(stage: gl::types::GLenum) -> u32 {
+    match stage {
+        gl::VERTEX_SHADER => 0,
+        gl::TESS_CONTROL_SHADER => 1,
+        gl::TESS_EVALUATION_SHADER => 2,
+        gl::GEOMETRY_SHADER => 3,
+        gl::FRAGMENT_SHADER => 4,
+        gl::COMPUTE_SHADER => 5,
+        _ => unreachable!(),
+    }
+}
+
+fn stage_bit(stage: gl::types::GLenum) -> gl::types::GLbitfield {
+    match stage {
+        gl::VERTEX_SHADER => gl::VERTEX_SHADER_BIT,
+        gl::TESS_CONTROL_SHADER => gl::TESS_CONTROL_SHADER_BIT,
+        gl::TESS_EVALUATION_SHADER => gl::TESS_EVALUATION_SHADER_BIT,
+        gl::GEOMETRY_SHADER => gl::GEOMETRY_SHADER_BIT,
+        gl::FRAGMENT_SHADER => gl::FRAGMENT_SHADER_BIT,
+        gl::COMPUTE_SHADER => gl::COMPUTE_SHADER_BIT,
+        _ => unreachable!(),
+    }
+}
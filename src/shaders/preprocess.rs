@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// `#define`s inserted after the `#version` line, kept sorted so equal sets compare and hash equal
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Defines(BTreeMap<String, String>);
+
+#[allow(dead_code)]
+impl Defines {
+    pub fn new() -> Defines {
+        Defines(BTreeMap::new())
+    }
+
+    pub fn define(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn flag(self, name: &str) -> Self {
+        self.define(name, "")
+    }
+
+    /// Adds every define of `other`, replacing the values of names both have
+    pub fn merge(mut self, other: Defines) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn inject(&self, source: &str) -> String {
+        if self.is_empty() {
+            return source.to_string();
+        }
+
+        let mut defines = String::new();
+        for (name, value) in self.iter() {
+            writeln!(defines, "#define {name} {value}").unwrap();
+        }
+
+        // `#version` has to stay the first directive, so everything goes right after it
+        let Some((version_end, version_line)) = find_version(source) else {
+            return format!("{defines}#line 1\n{source}");
+        };
+
+        let (head, tail) = source.split_at(version_end);
+        let newline = if head.ends_with('\n') { "" } else { "\n" };
+        // `#line` keeps compiler errors pointing at the lines of the original file
+        format!("{head}{newline}{defines}#line {}\n{tail}", version_line + 1)
+    }
+}
+
+/// Byte offset just past the `#version` line and its 1-based line number
+fn find_version(source: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        offset += line.len();
+
+        if line.trim_start().starts_with("#version") {
+            return Some((offset, index + 1));
+        }
+    }
+
+    None
+}
@@ -0,0 +1,456 @@
+use std::ffi::{c_char, CString};
+
+use opengl::gl;
+
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    pub name: String,
+    pub location: i32,
+    pub gl_type: gl::types::GLenum,
+    pub array_size: i32,
+}
+
+/// One per array element, named `name[i]` like the uniform locations
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct SamplerInfo {
+    pub name: String,
+    pub location: i32,
+    pub gl_type: gl::types::GLenum,
+    pub binding: i32,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct BlockMember {
+    pub name: String,
+    pub gl_type: gl::types::GLenum,
+    pub offset: i32,
+    pub size: i32,
+    pub array_size: i32,
+    pub array_stride: i32,
+    pub matrix_stride: i32,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct BlockInfo {
+    pub name: String,
+    pub index: u32,
+    pub binding: i32,
+    pub data_size: i32,
+    pub members: Vec<BlockMember>,
+}
+
+/// A stage input or output, for the vertex stage the inputs are the vertex attributes
+#[derive(Clone, Debug)]
+pub struct InterfaceVariable {
+    pub name: String,
+    pub location: i32,
+    pub gl_type: gl::types::GLenum,
+    pub array_size: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProgramReflection {
+    pub uniforms: Vec<UniformInfo>,
+    pub samplers: Vec<SamplerInfo>,
+    pub uniform_blocks: Vec<BlockInfo>,
+    pub storage_blocks: Vec<BlockInfo>,
+    pub inputs: Vec<InterfaceVariable>,
+    pub outputs: Vec<InterfaceVariable>,
+}
+
+#[allow(dead_code)]
+impl ProgramReflection {
+    pub fn new(program: u32) -> ProgramReflection {
+        let mut reflection = ProgramReflection::default();
+
+        for index in 0..active_resources(program, gl::UNIFORM) {
+            let [gl_type, array_size, location, block_index] = resource_properties(
+                program,
+                gl::UNIFORM,
+                index,
+                [gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION, gl::BLOCK_INDEX],
+            );
+
+            // Members of uniform blocks are reported through `uniform_blocks` instead
+            if block_index != -1 {
+                continue;
+            }
+
+            let name = resource_name(program, gl::UNIFORM, index);
+            let gl_type = gl_type as gl::types::GLenum;
+
+            if is_sampler(gl_type) {
+                // Every array element has its own location and binding
+                for (name, location) in array_elements(program, &name, location, array_size) {
+                    let mut binding = 0;
+                    unsafe {
+                        gl::GetUniformiv(program, location, &mut binding);
+                    }
+
+                    reflection.samplers.push(SamplerInfo {
+                        name,
+                        location,
+                        gl_type,
+                        binding,
+                    });
+                }
+            }
+
+            reflection.uniforms.push(UniformInfo {
+                name,
+                location,
+                gl_type,
+                array_size,
+            });
+        }
+
+        reflection.uniform_blocks = blocks(program, gl::UNIFORM_BLOCK, gl::UNIFORM);
+        reflection.storage_blocks = blocks(program, gl::SHADER_STORAGE_BLOCK, gl::BUFFER_VARIABLE);
+
+        reflection.inputs = interface_variables(program, gl::PROGRAM_INPUT);
+        reflection.outputs = interface_variables(program, gl::PROGRAM_OUTPUT);
+
+        reflection
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.iter().find(|uniform| uniform.name == name)
+    }
+
+    pub fn sampler(&self, name: &str) -> Option<&SamplerInfo> {
+        self.samplers.iter().find(|sampler| sampler.name == name)
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&BlockInfo> {
+        self.uniform_blocks.iter().find(|block| block.name == name)
+    }
+
+    pub fn storage_block(&self, name: &str) -> Option<&BlockInfo> {
+        self.storage_blocks.iter().find(|block| block.name == name)
+    }
+
+    pub fn input(&self, location: i32) -> Option<&InterfaceVariable> {
+        self.inputs.iter().find(|input| input.location == location)
+    }
+
+    pub fn output(&self, location: i32) -> Option<&InterfaceVariable> {
+        self.outputs
+            .iter()
+            .find(|output| output.location == location)
+    }
+}
+
+#[allow(dead_code)]
+impl BlockInfo {
+    pub fn member(&self, name: &str) -> Option<&BlockMember> {
+        self.members.iter().find(|member| member.name == name)
+    }
+}
+
+fn blocks(
+    program: u32,
+    interface: gl::types::GLenum,
+    member_interface: gl::types::GLenum,
+) -> Vec<BlockInfo> {
+    (0..active_resources(program, interface))
+        .map(|index| {
+            let [binding, data_size, member_count] = resource_properties(
+                program,
+                interface,
+                index,
+                [
+                    gl::BUFFER_BINDING,
+                    gl::BUFFER_DATA_SIZE,
+                    gl::NUM_ACTIVE_VARIABLES,
+                ],
+            );
+
+            let mut member_indices = vec![0; member_count as usize];
+            if member_count > 0 {
+                unsafe {
+                    gl::GetProgramResourceiv(
+                        program,
+                        interface,
+                        index,
+                        1,
+                        &gl::ACTIVE_VARIABLES,
+                        member_count,
+                        std::ptr::null_mut(),
+                        member_indices.as_mut_ptr(),
+                    );
+                }
+            }
+
+            let mut members: Vec<_> = member_indices
+                .into_iter()
+                .map(|member| {
+                    let member = member as u32;
+                    let [gl_type, array_size, offset, array_stride, matrix_stride] =
+                        resource_properties(
+                            program,
+                            member_interface,
+                            member,
+                            [
+                                gl::TYPE,
+                                gl::ARRAY_SIZE,
+                                gl::OFFSET,
+                                gl::ARRAY_STRIDE,
+                                gl::MATRIX_STRIDE,
+                            ],
+                        );
+
+                    let gl_type = gl_type as gl::types::GLenum;
+                    let element_size = glsl_type(gl_type).map_or(0, |ty| ty.size);
+                    let size = if array_size > 1 {
+                        array_stride * array_size
+                    } else {
+                        element_size
+                    };
+
+                    BlockMember {
+                        name: resource_name(program, member_interface, member),
+                        gl_type,
+                        offset,
+                        size,
+                        array_size,
+                        array_stride,
+                        matrix_stride,
+                    }
+                })
+                .collect();
+
+            members.sort_by_key(|member| member.offset);
+
+            BlockInfo {
+                name: resource_name(program, interface, index),
+                index,
+                binding,
+                data_size,
+                members,
+            }
+        })
+        .collect()
+}
+
+fn interface_variables(program: u32, interface: gl::types::GLenum) -> Vec<InterfaceVariable> {
+    let mut variables: Vec<_> = (0..active_resources(program, interface))
+        .filter_map(|index| {
+            let name = resource_name(program, interface, index);
+
+            // Built-ins like gl_VertexID or gl_Position aren't part of the user interface
+            if name.starts_with("gl_") {
+                return None;
+            }
+
+            let [gl_type, array_size, location] = resource_properties(
+                program,
+                interface,
+                index,
+                [gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION],
+            );
+
+            Some(InterfaceVariable {
+                name,
+                location,
+                gl_type: gl_type as gl::types::GLenum,
+                array_size,
+            })
+        })
+        .collect();
+
+    variables.sort_by_key(|variable| variable.location);
+    variables
+}
+
+/// `name[i]` and its location for every element of a uniform array reported as `name[0]`,
+/// just `name` otherwise. Element locations aren't guaranteed to be consecutive
+pub fn array_elements(
+    program: u32,
+    name: &str,
+    location: i32,
+    array_size: i32,
+) -> Vec<(String, i32)> {
+    let Some(base) = name.strip_suffix("[0]") else {
+        return vec![(name.to_string(), location)];
+    };
+
+    let mut elements = vec![(name.to_string(), location)];
+    for element in 1..array_size {
+        let name = format!("{base}[{element}]");
+        let c_name = CString::new(name.as_str()).unwrap();
+        let location =
+            unsafe { gl::GetProgramResourceLocation(program, gl::UNIFORM, c_name.as_ptr()) };
+        elements.push((name, location));
+    }
+    elements
+}
+
+fn active_resources(program: u32, interface: gl::types::GLenum) -> u32 {
+    let mut count = 0;
+    unsafe {
+        gl::GetProgramInterfaceiv(program, interface, gl::ACTIVE_RESOURCES, &mut count);
+    }
+    count as u32
+}
+
+fn resource_properties<const N: usize>(
+    program: u32,
+    interface: gl::types::GLenum,
+    index: u32,
+    properties: [gl::types::GLenum; N],
+) -> [i32; N] {
+    let mut values = [0; N];
+    unsafe {
+        gl::GetProgramResourceiv(
+            program,
+            interface,
+            index,
+            N as i32,
+            properties.as_ptr(),
+            N as i32,
+            std::ptr::null_mut(),
+            values.as_mut_ptr(),
+        );
+    }
+    values
+}
+
+fn resource_name(program: u32, interface: gl::types::GLenum, index: u32) -> String {
+    unsafe {
+        let mut buf_cap = 0;
+        gl::GetProgramInterfaceiv(program, interface, gl::MAX_NAME_LENGTH, &mut buf_cap);
+
+        let mut buf = vec![0u8; buf_cap as usize];
+        let mut buf_len = 0;
+        gl::GetProgramResourceName(
+            program,
+            interface,
+            index,
+            buf_cap,
+            &mut buf_len,
+            buf.as_mut_ptr() as *mut c_char,
+        );
+        buf.truncate(buf_len as usize);
+
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlslType {
+    pub name: &'static str,
+    /// The scalar type of a single component, `gl::FLOAT`, `gl::INT`, etc.
+    pub component: gl::types::GLenum,
+    pub components: i32,
+    /// Size in bytes of a single element, ignoring block layout padding
+    pub size: i32,
+}
+
+pub fn glsl_type(gl_type: gl::types::GLenum) -> Option<GlslType> {
+    let (name, component, components) = match gl_type {
+        gl::FLOAT => ("float", gl::FLOAT, 1),
+        gl::FLOAT_VEC2 => ("vec2", gl::FLOAT, 2),
+        gl::FLOAT_VEC3 => ("vec3", gl::FLOAT, 3),
+        gl::FLOAT_VEC4 => ("vec4", gl::FLOAT, 4),
+        gl::FLOAT_MAT2 => ("mat2", gl::FLOAT, 4),
+        gl::FLOAT_MAT3 => ("mat3", gl::FLOAT, 9),
+        gl::FLOAT_MAT4 => ("mat4", gl::FLOAT, 16),
+        gl::FLOAT_MAT2x3 => ("mat2x3", gl::FLOAT, 6),
+        gl::FLOAT_MAT2x4 => ("mat2x4", gl::FLOAT, 8),
+        gl::FLOAT_MAT3x2 => ("mat3x2", gl::FLOAT, 6),
+        gl::FLOAT_MAT3x4 => ("mat3x4", gl::FLOAT, 12),
+        gl::FLOAT_MAT4x2 => ("mat4x2", gl::FLOAT, 8),
+        gl::FLOAT_MAT4x3 => ("mat4x3", gl::FLOAT, 12),
+        gl::DOUBLE => ("double", gl::DOUBLE, 1),
+        gl::DOUBLE_VEC2 => ("dvec2", gl::DOUBLE, 2),
+        gl::DOUBLE_VEC3 => ("dvec3", gl::DOUBLE, 3),
+        gl::DOUBLE_VEC4 => ("dvec4", gl::DOUBLE, 4),
+        gl::DOUBLE_MAT2 => ("dmat2", gl::DOUBLE, 4),
+        gl::DOUBLE_MAT3 => ("dmat3", gl::DOUBLE, 9),
+        gl::DOUBLE_MAT4 => ("dmat4", gl::DOUBLE, 16),
+        gl::DOUBLE_MAT2x3 => ("dmat2x3", gl::DOUBLE, 6),
+        gl::DOUBLE_MAT2x4 => ("dmat2x4", gl::DOUBLE, 8),
+        gl::DOUBLE_MAT3x2 => ("dmat3x2", gl::DOUBLE, 6),
+        gl::DOUBLE_MAT3x4 => ("dmat3x4", gl::DOUBLE, 12),
+        gl::DOUBLE_MAT4x2 => ("dmat4x2", gl::DOUBLE, 8),
+        gl::DOUBLE_MAT4x3 => ("dmat4x3", gl::DOUBLE, 12),
+        gl::INT => ("int", gl::INT, 1),
+        gl::INT_VEC2 => ("ivec2", gl::INT, 2),
+        gl::INT_VEC3 => ("ivec3", gl::INT, 3),
+        gl::INT_VEC4 => ("ivec4", gl::INT, 4),
+        gl::UNSIGNED_INT => ("uint", gl::UNSIGNED_INT, 1),
+        gl::UNSIGNED_INT_VEC2 => ("uvec2", gl::UNSIGNED_INT, 2),
+        gl::UNSIGNED_INT_VEC3 => ("uvec3", gl::UNSIGNED_INT, 3),
+        gl::UNSIGNED_INT_VEC4 => ("uvec4", gl::UNSIGNED_INT, 4),
+        gl::BOOL => ("bool", gl::BOOL, 1),
+        gl::BOOL_VEC2 => ("bvec2", gl::BOOL, 2),
+        gl::BOOL_VEC3 => ("bvec3", gl::BOOL, 3),
+        gl::BOOL_VEC4 => ("bvec4", gl::BOOL, 4),
+        _ => return None,
+    };
+
+    let component_size = if component == gl::DOUBLE { 8 } else { 4 };
+
+    Some(GlslType {
+        name,
+        component,
+        components,
+        size: components * component_size,
+    })
+}
+
+pub fn is_sampler(gl_type: gl::types::GLenum) -> bool {
+    matches!(
+        gl_type,
+        gl::SAMPLER_1D
+            | gl::SAMPLER_2D
+            | gl::SAMPLER_3D
+            | gl::SAMPLER_CUBE
+            | gl::SAMPLER_1D_SHADOW
+            | gl::SAMPLER_2D_SHADOW
+            | gl::SAMPLER_1D_ARRAY
+            | gl::SAMPLER_2D_ARRAY
+            | gl::SAMPLER_CUBE_MAP_ARRAY
+            | gl::SAMPLER_1D_ARRAY_SHADOW
+            | gl::SAMPLER_2D_ARRAY_SHADOW
+            | gl::SAMPLER_2D_MULTISAMPLE
+            | gl::SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::SAMPLER_CUBE_SHADOW
+            | gl::SAMPLER_CUBE_MAP_ARRAY_SHADOW
+            | gl::SAMPLER_BUFFER
+            | gl::SAMPLER_2D_RECT
+            | gl::SAMPLER_2D_RECT_SHADOW
+            | gl::INT_SAMPLER_1D
+            | gl::INT_SAMPLER_2D
+            | gl::INT_SAMPLER_3D
+            | gl::INT_SAMPLER_CUBE
+            | gl::INT_SAMPLER_1D_ARRAY
+            | gl::INT_SAMPLER_2D_ARRAY
+            | gl::INT_SAMPLER_CUBE_MAP_ARRAY
+            | gl::INT_SAMPLER_2D_MULTISAMPLE
+            | gl::INT_SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::INT_SAMPLER_BUFFER
+            | gl::INT_SAMPLER_2D_RECT
+            | gl::UNSIGNED_INT_SAMPLER_1D
+            | gl::UNSIGNED_INT_SAMPLER_2D
+            | gl::UNSIGNED_INT_SAMPLER_3D
+            | gl::UNSIGNED_INT_SAMPLER_CUBE
+            | gl::UNSIGNED_INT_SAMPLER_1D_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_CUBE_MAP_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_2D_MULTISAMPLE
+            | gl::UNSIGNED_INT_SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_BUFFER
+            | gl::UNSIGNED_INT_SAMPLER_2D_RECT
+    )
+}
+
+pub fn type_name(gl_type: gl::types::GLenum) -> String {
+    match glsl_type(gl_type) {
+        Some(ty) => ty.name.to_string(),
+        None => format!("0x{gl_type:04X}"),
+    }
+}
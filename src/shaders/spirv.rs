@@ -0,0 +1,250 @@
+use std::ffi::{CStr, CString};
+
+use opengl::gl;
+
+use crate::assets;
+
+use super::{stage_name, Shader};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// A precompiled SPIR-V stage, specialized and linked through `Shader::from_spirv`
+pub struct SpirvModule {
+    stage: gl::types::GLenum,
+    binary: Vec<u8>,
+    entry_point: CString,
+    constant_indices: Vec<u32>,
+    constant_values: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl SpirvModule {
+    pub fn new(stage: gl::types::GLenum, spirv_file: &str) -> Result<SpirvModule, String> {
+        let binary = assets::read(spirv_file).map_err(|error| {
+            format!("{} Shader Error: {spirv_file}: {error}", stage_name(stage))
+        })?;
+
+        SpirvModule::from_bytes(stage, binary)
+    }
+
+    /// Modules in either byte order are accepted, the magic number tells which one the file
+    /// uses. Byte swapped modules are converted to the host order GL reads
+    pub fn from_bytes(
+        stage: gl::types::GLenum,
+        mut binary: Vec<u8>,
+    ) -> Result<SpirvModule, String> {
+        if binary.len() % 4 != 0 || binary.len() < 20 {
+            return Err(format!(
+                "SPIR-V module is {} bytes, which is not a whole header and number of words",
+                binary.len()
+            ));
+        }
+
+        let magic = u32::from_ne_bytes(binary[..4].try_into().unwrap());
+        if magic == SPIRV_MAGIC.swap_bytes() {
+            for word in binary.chunks_exact_mut(4) {
+                word.reverse();
+            }
+        } else if magic != SPIRV_MAGIC {
+            return Err(format!(
+                "SPIR-V module has magic number 0x{magic:08X}, expected 0x{SPIRV_MAGIC:08X}"
+            ));
+        }
+
+        Ok(SpirvModule {
+            stage,
+            binary,
+            entry_point: CString::new("main").unwrap(),
+            constant_indices: Vec::new(),
+            constant_values: Vec::new(),
+        })
+    }
+
+    pub fn entry_point(mut self, entry_point: &str) -> Self {
+        self.entry_point = CString::new(entry_point).unwrap();
+        self
+    }
+
+    /// Sets the `layout(constant_id = index)` constant to `value`
+    pub fn specialize(mut self, index: u32, value: impl SpecializationValue) -> Self {
+        let value = value.bits();
+
+        match self.constant_indices.iter().position(|&i| i == index) {
+            Some(position) => self.constant_values[position] = value,
+            None => {
+                self.constant_indices.push(index);
+                self.constant_values.push(value);
+            }
+        }
+
+        self
+    }
+
+    pub fn stage(&self) -> gl::types::GLenum {
+        self.stage
+    }
+
+    unsafe fn compile(&self) -> u32 {
+        let shader = gl::CreateShader(self.stage);
+
+        gl::ShaderBinary(
+            1,
+            &shader,
+            gl::SHADER_BINARY_FORMAT_SPIR_V,
+            self.binary.as_ptr() as *const _,
+            self.binary.len() as i32,
+        );
+
+        gl::SpecializeShader(
+            shader,
+            self.entry_point.as_ptr(),
+            self.constant_indices.len() as u32,
+            self.constant_indices.as_ptr(),
+            self.constant_values.as_ptr(),
+        );
+
+        if !Shader::get_success(shader) {
+            let log = Shader::get_info_log(shader);
+            panic!(
+                "{} Shader Specialization Error ({:?}): {log:?}",
+                stage_name(self.stage),
+                self.entry_point
+            );
+        }
+
+        shader
+    }
+}
+
+/// Specialization constants are passed to GL as raw 32-bit words
+pub trait SpecializationValue {
+    fn bits(self) -> u32;
+}
+
+impl SpecializationValue for u32 {
+    fn bits(self) -> u32 {
+        self
+    }
+}
+
+impl SpecializationValue for i32 {
+    fn bits(self) -> u32 {
+        self as u32
+    }
+}
+
+impl SpecializationValue for f32 {
+    fn bits(self) -> u32 {
+        self.to_bits()
+    }
+}
+
+impl SpecializationValue for bool {
+    fn bits(self) -> u32 {
+        self as u32
+    }
+}
+
+/// SPIR-V needs either a 4.6 context or `ARB_gl_spirv`, which is what `SpecializeShader` falls
+/// back to
+pub fn spirv_supported() -> bool {
+    if !gl::SpecializeShader::is_loaded() {
+        return false;
+    }
+
+    let (mut major, mut minor, mut extensions) = (0, 0, 0);
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extensions);
+    }
+
+    if (major, minor) >= (4, 6) {
+        return true;
+    }
+
+    (0..extensions as u32).any(|index| unsafe {
+        let extension = gl::GetStringi(gl::EXTENSIONS, index);
+        !extension.is_null()
+            && CStr::from_ptr(extension as *const _).to_bytes() == b"GL_ARB_gl_spirv"
+    })
+}
+
+#[allow(dead_code)]
+impl Shader {
+    pub fn from_spirv(modules: &[SpirvModule]) -> Shader {
+        assert!(
+            spirv_supported(),
+            "SPIR-V shaders need OpenGL 4.6 or ARB_gl_spirv"
+        );
+
+        unsafe {
+            let id = gl::CreateProgram();
+
+            let shaders: Vec<u32> = modules
+                .iter()
+                .map(|module| {
+                    let shader = module.compile();
+                    gl::AttachShader(id, shader);
+                    shader
+                })
+                .collect();
+
+            gl::LinkProgram(id);
+
+            for shader in shaders {
+                gl::DeleteShader(shader);
+            }
+
+            Shader::from_program(id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The header words of an empty module: magic, version 1.0, generator, bound and schema
+    fn header() -> [u32; 5] {
+        [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0]
+    }
+
+    #[test]
+    fn host_order_modules_are_kept_as_they_are() {
+        let bytes: Vec<u8> = header()
+            .iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect();
+
+        let module = SpirvModule::from_bytes(gl::VERTEX_SHADER, bytes.clone()).unwrap();
+        assert_eq!(module.binary, bytes);
+    }
+
+    #[test]
+    fn byte_swapped_modules_are_converted_to_host_order() {
+        let swapped: Vec<u8> = header()
+            .iter()
+            .flat_map(|word| word.swap_bytes().to_ne_bytes())
+            .collect();
+        let native: Vec<u8> = header()
+            .iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect();
+
+        let module = SpirvModule::from_bytes(gl::FRAGMENT_SHADER, swapped).unwrap();
+        assert_eq!(module.binary, native);
+    }
+
+    #[test]
+    fn broken_modules_are_errors() {
+        let bytes: Vec<u8> = header()
+            .iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect();
+
+        assert!(SpirvModule::from_bytes(gl::VERTEX_SHADER, bytes[..19].to_vec()).is_err());
+        assert!(SpirvModule::from_bytes(gl::VERTEX_SHADER, bytes[..16].to_vec()).is_err());
+        assert!(SpirvModule::from_bytes(gl::VERTEX_SHADER, vec![0; 20]).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use std::mem;
+
+use nalgebra_glm::{self as glm, Mat4, Vec3};
+use opengl::gl;
+
+use crate::buffers::UBO;
+use crate::camera;
+use crate::lighting::Lights;
+use crate::shaders::{Defines, Shader};
+use crate::textures::TextureHandle;
+
+/// Size of `cascadeMatrices`, injected by `Shadows::defines`. The splits are packed into one
+/// `vec4`, so there are at most 4
+pub const MAX_CASCADES: usize = 4;
+
+/// Binding point of `ShadowBlock`, after `MatrixBlock` and `LightBlock`
+pub const SHADOW_BLOCK_BINDING: u32 = 2;
+
+/// Up to this shadow distance a single cascade has enough resolution
+const SINGLE_CASCADE_DISTANCE: f32 = 25.0;
+
+/// How far behind a cascade casters are still rendered into it
+const CASTER_DISTANCE: f32 = 10.0;
+
+const POINT_NEAR: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// Width and height of every directional cascade
+    pub resolution: i32,
+    /// Width and height of every point light cube face
+    pub point_resolution: i32,
+    /// Minimum depth bias of the directional shadows
+    pub bias: f32,
+    /// Depth bias added at grazing angles
+    pub slope_bias: f32,
+    /// Depth bias of the point shadows, in units of `point_far`
+    pub point_bias: f32,
+    /// PCF kernel radius in texels, 0 only uses the hardware 2x2 filter
+    pub pcf_radius: i32,
+    /// PCF disk radius of the point shadows, as a fraction of the distance to the light
+    pub point_pcf_radius: f32,
+    /// Receivers farther from the camera than this are not shadowed
+    pub distance: f32,
+    /// Used once `distance` is larger than a single cascade covers
+    pub cascades: usize,
+    /// Blend between uniform (0.0) and logarithmic (1.0) cascade splits
+    pub split_lambda: f32,
+    /// Range of the point light shadow
+    pub point_far: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            resolution: 2048,
+            point_resolution: 1024,
+            bias: 0.0005,
+            slope_bias: 0.005,
+            point_bias: 0.005,
+            pcf_radius: 1,
+            // About 1/50 of the distance smooths the edges
+            point_pcf_radius: 0.02,
+            // Far enough to split into cascades
+            distance: 50.0,
+            cascades: MAX_CASCADES,
+            split_lambda: 0.75,
+            point_far: 25.0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl ShadowSettings {
+    pub fn cascade_count(&self) -> usize {
+        if self.distance <= SINGLE_CASCADE_DISTANCE {
+            1
+        } else {
+            self.cascades.clamp(1, MAX_CASCADES)
+        }
+    }
+}
+
+/// Mirrors `ShadowBlock` in `fragment.frag`
+#[repr(C)]
+struct ShadowBlockData {
+    cascade_matrices: [[f32; 16]; MAX_CASCADES],
+    cascade_splits: [f32; 4],
+    directional_params: [f32; 4],
+    point_position: [f32; 4],
+    point_params: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Cascade {
+    projection: Mat4,
+    view: Mat4,
+    /// View depth where the cascade ends
+    split: f32,
+}
+
+/// A depth texture with compare mode set, rendered through its own framebuffer
+struct DepthTarget {
+    texture: TextureHandle,
+    framebuffer: u32,
+    resolution: i32,
+}
+
+impl DepthTarget {
+    fn new(target: gl::types::GLenum, resolution: i32, layers: i32) -> DepthTarget {
+        let mut texture = 0;
+        let mut framebuffer = 0;
+
+        unsafe {
+            gl::CreateTextures(target, 1, &mut texture);
+
+            if target == gl::TEXTURE_2D_ARRAY {
+                gl::TextureStorage3D(
+                    texture,
+                    1,
+                    gl::DEPTH_COMPONENT32F,
+                    resolution,
+                    resolution,
+                    layers,
+                );
+
+                // Everything outside the light frustum is lit
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+                gl::TextureParameterfv(texture, gl::TEXTURE_BORDER_COLOR, [1.0; 4].as_ptr());
+            } else {
+                gl::TextureStorage2D(texture, 1, gl::DEPTH_COMPONENT32F, resolution, resolution);
+
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+            }
+
+            // Linear filtering with compare mode gives 2x2 PCF for free
+            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TextureParameteri(
+                texture,
+                gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as i32,
+            );
+            gl::TextureParameteri(texture, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+
+            gl::CreateFramebuffers(1, &mut framebuffer);
+            gl::NamedFramebufferDrawBuffer(framebuffer, gl::NONE);
+            gl::NamedFramebufferReadBuffer(framebuffer, gl::NONE);
+            gl::NamedFramebufferTextureLayer(framebuffer, gl::DEPTH_ATTACHMENT, texture, 0, 0);
+
+            let status = gl::CheckNamedFramebufferStatus(framebuffer, gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                panic!("Shadow Framebuffer Error: incomplete, status {status:#x}");
+            }
+        }
+
+        DepthTarget {
+            texture: TextureHandle::new(texture, "shadow map"),
+            framebuffer,
+            resolution,
+        }
+    }
+
+    /// Binds the framebuffer with `layer` attached and clears it
+    fn begin(&self, layer: i32) {
+        unsafe {
+            gl::NamedFramebufferTextureLayer(
+                self.framebuffer,
+                gl::DEPTH_ATTACHMENT,
+                self.texture.id(),
+                0,
+                layer,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.resolution, self.resolution);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+}
+
+impl Drop for DepthTarget {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+        }
+    }
+}
+
+/// Shadow maps for the first directional light, cascaded over the camera frustum, and for the
+/// first point light as a cube map
+pub struct Shadows<'a> {
+    settings: ShadowSettings,
+    directional: DepthTarget,
+    point: DepthTarget,
+    directional_shader: &'a Shader,
+    point_shader: &'a Shader,
+    ubo: UBO,
+}
+
+#[allow(dead_code)]
+impl<'a> Shadows<'a> {
+    /// `directional_shader` and `point_shader` are `shadow_depth.vert`/`shadow_depth.frag`,
+    /// the latter compiled with `POINT_SHADOW`, both attached to the `MatrixBlock` of the scene
+    pub fn new(
+        settings: ShadowSettings,
+        directional_shader: &'a Shader,
+        point_shader: &'a Shader,
+    ) -> Shadows<'a> {
+        Shadows {
+            directional: DepthTarget::new(
+                gl::TEXTURE_2D_ARRAY,
+                settings.resolution,
+                settings.cascade_count() as i32,
+            ),
+            point: DepthTarget::new(gl::TEXTURE_CUBE_MAP, settings.point_resolution, 6),
+            settings,
+            directional_shader,
+            point_shader,
+            ubo: UBO::with_binding(mem::size_of::<ShadowBlockData>(), SHADOW_BLOCK_BINDING),
+        }
+    }
+
+    /// The cascade count the shaders have to be compiled with to match `ShadowBlockData`
+    pub fn defines() -> Defines {
+        Defines::new().define("MAX_CASCADES", MAX_CASCADES)
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    /// The `sampler2DArrayShadow` with one layer per cascade
+    pub fn directional_texture(&self) -> &TextureHandle {
+        &self.directional.texture
+    }
+
+    /// The `samplerCubeShadow` storing distance to the light divided by `point_far`
+    pub fn point_texture(&self) -> &TextureHandle {
+        &self.point.texture
+    }
+
+    /// Attaches `ShadowBlock` to a shader that samples the shadow maps
+    pub fn attach_shader(&self, shader: &Shader) {
+        self.ubo.attach_new_shader(shader, "ShadowBlock");
+    }
+
+    /// Renders every shadow map with `draw_scene`, which draws the casters by writing their
+    /// model matrices with `next_attribute_reduced`. Leaves `matrix_block` cleared and restores
+    /// the default framebuffer and viewport
+    pub fn render(
+        &self,
+        lights: &Lights,
+        view: &Mat4,
+        projection: &Mat4,
+        matrix_block: &mut UBO,
+        mut draw_scene: impl FnMut(&mut UBO),
+    ) {
+        let mut viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+
+        let mut data = ShadowBlockData {
+            cascade_matrices: [[0.0; 16]; MAX_CASCADES],
+            cascade_splits: [0.0; 4],
+            directional_params: [
+                self.settings.bias,
+                self.settings.slope_bias,
+                self.settings.pcf_radius as f32,
+                0.0,
+            ],
+            point_position: [0.0; 4],
+            point_params: [self.settings.point_bias, 0.0, 0.0, 0.0],
+        };
+
+        if let Some(light) = lights.directional.first() {
+            let cascades = self.fit_cascades(&light.direction, view, projection);
+            self.directional_shader.use_program();
+
+            for (layer, cascade) in cascades.iter().enumerate() {
+                self.directional.begin(layer as i32);
+                draw_pass(
+                    matrix_block,
+                    &cascade.projection,
+                    &cascade.view,
+                    &mut draw_scene,
+                );
+
+                let matrix = cascade.projection * cascade.view;
+                data.cascade_matrices[layer].copy_from_slice(matrix.as_slice());
+                data.cascade_splits[layer] = cascade.split;
+            }
+
+            data.directional_params[3] = cascades.len() as f32;
+        }
+
+        if let Some(light) = lights.point.first() {
+            let far = self.settings.point_far;
+            let projection = glm::perspective(1.0, 90.0_f32.to_radians(), POINT_NEAR, far);
+
+            self.point_shader.use_program();
+            self.point_shader
+                .set_uniform("lightPosition", light.position);
+            self.point_shader.set_uniform("farPlane", far);
+
+            for (face, (direction, up)) in cube_faces().iter().enumerate() {
+                let view = glm::look_at(&light.position, &(light.position + direction), up);
+
+                self.point.begin(face as i32);
+                draw_pass(matrix_block, &projection, &view, &mut draw_scene);
+            }
+
+            data.point_position = [light.position.x, light.position.y, light.position.z, far];
+            data.point_params[1] = self.settings.point_pcf_radius;
+            data.point_params[2] = 1.0;
+        }
+
+        self.ubo.write(0, std::slice::from_ref(&data));
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+    }
+
+    /// Splits the camera frustum up to `distance` and fits an orthographic light frustum around
+    /// each slice
+    fn fit_cascades(&self, direction: &Vec3, view: &Mat4, projection: &Mat4) -> Vec<Cascade> {
+        let (camera_near, camera_far) = camera::depth_range(projection);
+        let (near, far) = (camera_near, camera_far.min(self.settings.distance));
+        let count = self.settings.cascade_count();
+
+        let corners = frustum_corners(&(projection * view));
+
+        let direction = glm::normalize(direction);
+        let up = if direction.y.abs() > 0.99 {
+            glm::vec3(0.0, 0.0, 1.0)
+        } else {
+            glm::vec3(0.0, 1.0, 0.0)
+        };
+
+        let mut start = near;
+        (1..=count)
+            .map(|index| {
+                let fraction = index as f32 / count as f32;
+                let uniform = near + (far - near) * fraction;
+                let logarithmic = near * (far / near).powf(fraction);
+                let split = glm::lerp_scalar(uniform, logarithmic, self.settings.split_lambda);
+
+                // View depth changes linearly along the edges from the near to the far corners
+                let slice: Vec<Vec3> = [start, split]
+                    .iter()
+                    .flat_map(|depth| {
+                        let t = (depth - camera_near) / (camera_far - camera_near);
+                        (0..4).map(move |edge| corners[edge].lerp(&corners[edge + 4], t))
+                    })
+                    .collect();
+                start = split;
+
+                let center = slice.iter().sum::<Vec3>() / slice.len() as f32;
+                // A bounding sphere keeps the size constant while the camera rotates
+                let radius = slice
+                    .iter()
+                    .map(|corner| glm::distance(corner, &center))
+                    .fold(0.0, f32::max)
+                    .ceil();
+
+                let eye = center - direction * (radius + CASTER_DISTANCE);
+                let view = glm::look_at(&eye, &center, &up);
+                let mut projection = glm::ortho(
+                    -radius,
+                    radius,
+                    -radius,
+                    radius,
+                    0.0,
+                    2.0 * radius + CASTER_DISTANCE,
+                );
+
+                // Moves the frustum in whole texels so edges don't shimmer when the camera moves
+                let texels = self.settings.resolution as f32 / 2.0;
+                let origin = (projection * view) * glm::vec4(0.0, 0.0, 0.0, 1.0) * texels;
+                let offset = (origin.xy().map(f32::round) - origin.xy()) / texels;
+                projection[(0, 3)] += offset.x;
+                projection[(1, 3)] += offset.y;
+
+                Cascade {
+                    projection,
+                    view,
+                    split,
+                }
+            })
+            .collect()
+    }
+}
+
+fn draw_pass(
+    matrix_block: &mut UBO,
+    projection: &Mat4,
+    view: &Mat4,
+    draw_scene: &mut impl FnMut(&mut UBO),
+) {
+    matrix_block.clear();
+    matrix_block.next_attribute(projection);
+    matrix_block.next_attribute(view);
+    draw_scene(matrix_block);
+    matrix_block.clear();
+}
+
+/// World space corners of the frustum, the near plane first
+fn frustum_corners(view_projection: &Mat4) -> [Vec3; 8] {
+    let inverse = glm::inverse(view_projection);
+    let mut corners = [Vec3::zeros(); 8];
+
+    for (index, corner) in corners.iter_mut().enumerate() {
+        let x = if index & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if index & 2 == 0 { -1.0 } else { 1.0 };
+        let z = if index & 4 == 0 { -1.0 } else { 1.0 };
+
+        let point = inverse * glm::vec4(x, y, z, 1.0);
+        *corner = point.xyz() / point.w;
+    }
+
+    corners
+}
+
+/// Look direction and up vector of every cube map face, in `TEXTURE_CUBE_MAP_POSITIVE_X` order
+fn cube_faces() -> [(Vec3, Vec3); 6] {
+    [
+        (glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+        (glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, -1.0, 0.0)),
+        (glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+        (glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 0.0, -1.0)),
+        (glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, -1.0, 0.0)),
+        (glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, -1.0, 0.0)),
+    ]
+}
@@ -0,0 +1,51 @@
+use nalgebra_glm::{self as glm, Mat4};
+use opengl::gl;
+
+use crate::fullscreen::FullscreenTriangle;
+use crate::material::Material;
+use crate::shaders::Shader;
+use crate::textures::TextureHandle;
+
+/// Draws a cube map in place of a clear color. Drawn after the opaque geometry, so only the
+/// pixels nothing covers pass the depth test
+pub struct Skybox<'a> {
+    material: Material<'a>,
+    triangle: FullscreenTriangle,
+}
+
+#[allow(dead_code)]
+impl<'a> Skybox<'a> {
+    /// `shader` is `skybox.vert` with `skybox.frag`
+    pub fn new(shader: &'a Shader, cube_map: &TextureHandle) -> Skybox<'a> {
+        Skybox {
+            material: Material::new(shader).with_texture("skybox", cube_map),
+            triangle: FullscreenTriangle::new(),
+        }
+    }
+
+    pub fn set_cube_map(&mut self, cube_map: &TextureHandle) {
+        self.material.set_texture("skybox", cube_map);
+    }
+
+    pub fn draw(&mut self, view: &Mat4, projection: &Mat4) {
+        let rotation = glm::mat3_to_mat4(&glm::mat4_to_mat3(view));
+        self.material.set(
+            "inverseViewProjection",
+            glm::inverse(&(projection * rotation)),
+        );
+
+        unsafe {
+            // The sky is exactly at the far plane, where the cleared depth is
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+        }
+
+        self.material.apply();
+        self.triangle.draw();
+
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+}
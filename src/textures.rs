@@ -1,62 +1,417 @@
-use std::{ffi::c_void, ops::Index};
+use std::ffi::c_void;
 
-use image::DynamicImage;
+use image::{DynamicImage, GenericImageView};
 use opengl::gl;
 
+use crate::ibl;
+
+pub(crate) mod atlas;
+mod dds;
+mod files;
+mod ktx2;
+mod manager;
+
+pub(crate) use self::files::TextureFile;
+pub(crate) use self::manager::{TextureBindings, TextureHandle, TextureManager, TextureOptions};
+
+/// Pixel transfer format, pixel type and sized internal format for an image's color type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PixelFormat {
+    pub format: gl::types::GLenum,
+    pub pixel_type: gl::types::GLenum,
+    pub internalformat: gl::types::GLenum,
+}
+
+impl PixelFormat {
+    /// Linear formats for the image's color type. 16 bit images are stored as half floats, GL
+    /// normalizes the unsigned shorts to [0, 1] on upload. Float images keep 32 bits. RGB float
+    /// images come from `.hdr` files as well as OpenEXR without alpha, and EXR can hold more
+    /// than RGBE's 8 bit mantissas. Color types without a matching GL format are `None`,
+    /// `TextureBuilder` converts those to RGBA8 first
+    pub fn of(image: &DynamicImage) -> Option<PixelFormat> {
+        let (format, pixel_type, internalformat) = match image {
+            DynamicImage::ImageLuma8(_) => (gl::RED, gl::UNSIGNED_BYTE, gl::R8),
+            DynamicImage::ImageLumaA8(_) => (gl::RG, gl::UNSIGNED_BYTE, gl::RG8),
+            DynamicImage::ImageRgb8(_) => (gl::RGB, gl::UNSIGNED_BYTE, gl::RGB8),
+            DynamicImage::ImageRgba8(_) => (gl::RGBA, gl::UNSIGNED_BYTE, gl::RGBA8),
+            DynamicImage::ImageLuma16(_) => (gl::RED, gl::UNSIGNED_SHORT, gl::R16F),
+            DynamicImage::ImageLumaA16(_) => (gl::RG, gl::UNSIGNED_SHORT, gl::RG16F),
+            DynamicImage::ImageRgb16(_) => (gl::RGB, gl::UNSIGNED_SHORT, gl::RGB16F),
+            DynamicImage::ImageRgba16(_) => (gl::RGBA, gl::UNSIGNED_SHORT, gl::RGBA16F),
+            DynamicImage::ImageRgb32F(_) => (gl::RGB, gl::FLOAT, gl::RGB32F),
+            DynamicImage::ImageRgba32F(_) => (gl::RGBA, gl::FLOAT, gl::RGBA32F),
+            _ => return None,
+        };
+
+        Some(PixelFormat {
+            format,
+            pixel_type,
+            internalformat,
+        })
+    }
+
+    /// The sRGB variant of the internal format, only 8 bit RGB and RGBA have one
+    fn srgb(self) -> Option<PixelFormat> {
+        let internalformat = match self.internalformat {
+            gl::RGB8 => gl::SRGB8,
+            gl::RGBA8 => gl::SRGB8_ALPHA8,
+            _ => return None,
+        };
+
+        Some(PixelFormat {
+            internalformat,
+            ..self
+        })
+    }
+}
+
+/// Levels of a full mip chain, down to 1x1
+pub(crate) fn mip_count(width: u32, height: u32) -> i32 {
+    (32 - width.max(height).max(1).leading_zeros()) as i32
+}
+
+/// `MAX_TEXTURE_MAX_ANISOTROPY`, 1.0 when anisotropic filtering is not supported
+pub(crate) fn max_anisotropy() -> f32 {
+    let mut max = 1.0;
+    unsafe {
+        gl::GetFloatv(gl::MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+    }
+    max.max(1.0)
+}
+
+/// The largest `UNPACK_ALIGNMENT` that divides a row of `row_bytes`, image rows have no padding
+pub(crate) fn unpack_alignment(row_bytes: usize) -> i32 {
+    [8, 4, 2, 1]
+        .into_iter()
+        .find(|&alignment| row_bytes % alignment == 0)
+        .unwrap_or(1) as i32
+}
+
 pub(crate) struct TextureBuilder {
     texture: u32,
-    image: DynamicImage,
-    internal_format: gl::types::GLenum,
-    internalformat: gl::types::GLenum,
+    target: gl::types::GLenum,
+    /// The image of a 2D texture, the faces of a cube map in +X, -X, +Y, -Y, +Z, -Z order, the
+    /// layers of an array, the slices of a 3D texture, or the panorama of an
+    /// `equirect_cube_map`
+    images: Vec<DynamicImage>,
+    /// Face size of a cube map rendered from a panorama
+    equirect_size: Option<u32>,
+    format: PixelFormat,
 }
 
 #[allow(dead_code)]
 impl TextureBuilder {
-    pub fn new(
-        image: DynamicImage,
-        internal_format: gl::types::GLenum,
-        internalformat: gl::types::GLenum,
+    /// Picks the formats from the image's color type, as linear data. Use `try_srgb` for color
+    /// textures
+    pub fn new(image: DynamicImage) -> TextureBuilder {
+        TextureBuilder::create(gl::TEXTURE_2D, vec![image], None)
+    }
+
+    /// A cube map from six square faces of one size and color type, in +X, -X, +Y, -Y, +Z, -Z
+    /// order. Cube map faces start at the top left like images, so they are not flipped
+    pub fn cube_map(faces: [DynamicImage; 6]) -> TextureBuilder {
+        let (width, height) = faces[0].dimensions();
+        assert!(
+            width == height,
+            "Texture Error: cube map faces have to be square, not {width}x{height}"
+        );
+
+        check_layers("cube map face", &faces);
+        TextureBuilder::create(gl::TEXTURE_CUBE_MAP, faces.into(), None)
+    }
+
+    /// A `TEXTURE_2D_ARRAY` with one layer per image, of one size and color type. Shaders pick
+    /// the layer with the third texture coordinate, like a tile ID
+    pub fn array(layers: Vec<DynamicImage>) -> TextureBuilder {
+        check_layers("array layer", &layers);
+        TextureBuilder::create(gl::TEXTURE_2D_ARRAY, layers, None)
+    }
+
+    /// A `TEXTURE_3D` stacking the slices along z, of one size and color type
+    pub fn volume(slices: Vec<DynamicImage>) -> TextureBuilder {
+        check_layers("3D texture slice", &slices);
+        TextureBuilder::create(gl::TEXTURE_3D, slices, None)
+    }
+
+    /// A cube map with faces of `size`, rendered from an equirectangular panorama on the GPU by
+    /// `sub_texture`. RGB formats become RGBA, so the faces can be rendered to
+    pub fn equirect_cube_map(panorama: DynamicImage, size: u32) -> TextureBuilder {
+        TextureBuilder::create(gl::TEXTURE_CUBE_MAP, vec![panorama], Some(size))
+    }
+
+    fn create(
+        target: gl::types::GLenum,
+        images: Vec<DynamicImage>,
+        equirect_size: Option<u32>,
     ) -> TextureBuilder {
+        let images: Vec<DynamicImage> = images
+            .into_iter()
+            .map(|image| match PixelFormat::of(&image) {
+                Some(_) => image,
+                None => DynamicImage::ImageRgba8(image.to_rgba8()),
+            })
+            .collect();
+        let format = PixelFormat::of(&images[0]).unwrap();
+
         unsafe {
             let mut texture = 0;
-            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut texture);
-            TextureBuilder {
+            gl::CreateTextures(target, 1, &mut texture);
+
+            // Gray images read as gray instead of red
+            match format.format {
+                gl::RED => set_swizzle(texture, [gl::RED, gl::RED, gl::RED, gl::ONE]),
+                gl::RG => set_swizzle(texture, [gl::RED, gl::RED, gl::RED, gl::GREEN]),
+                _ => (),
+            }
+
+            let builder = TextureBuilder {
                 texture,
-                image,
-                internal_format,
-                internalformat,
+                target,
+                images,
+                equirect_size,
+                format,
+            };
+
+            TextureBuilder {
+                format: builder.storage_format(format),
+                ..builder
             }
         }
     }
 
+    /// Stores the image as sRGB, so sampling returns linear colors. Gray 8 bit images are
+    /// expanded to RGB since there is no gray sRGB format. Errors for 16 bit and float images,
+    /// those are always linear. Call before `texture_storage`
+    pub fn try_srgb(mut self, srgb: bool) -> Result<Self, String> {
+        if !srgb {
+            self.format = self.storage_format(PixelFormat::of(&self.images[0]).unwrap());
+            return Ok(self);
+        }
+
+        let expanded = |image: &DynamicImage| match image {
+            DynamicImage::ImageLuma8(_) => Some(DynamicImage::ImageRgb8(image.to_rgb8())),
+            DynamicImage::ImageLumaA8(_) => Some(DynamicImage::ImageRgba8(image.to_rgba8())),
+            _ => None,
+        };
+
+        let first = expanded(&self.images[0]);
+        let first = first.as_ref().unwrap_or(&self.images[0]);
+        let Some(format) = PixelFormat::of(first).and_then(PixelFormat::srgb) else {
+            let color = self.images[0].color();
+            unsafe {
+                gl::DeleteTextures(1, &self.texture);
+            }
+            return Err(format!("no sRGB format for {color:?} images"));
+        };
+
+        for image in &mut self.images {
+            if let Some(rgb) = expanded(image) {
+                *image = rgb;
+            }
+        }
+
+        unsafe {
+            // The expanded image has real color channels
+            set_swizzle(self.texture, [gl::RED, gl::GREEN, gl::BLUE, gl::ALPHA]);
+        }
+
+        self.format = self.storage_format(format);
+        Ok(self)
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Faces rendered from a panorama need a color renderable format
+    fn storage_format(&self, format: PixelFormat) -> PixelFormat {
+        if self.equirect_size.is_none() {
+            return format;
+        }
+
+        let internalformat = match format.internalformat {
+            gl::RGB8 => gl::RGBA8,
+            gl::SRGB8 => gl::SRGB8_ALPHA8,
+            gl::RGB16F => gl::RGBA16F,
+            gl::RGB32F => gl::RGBA32F,
+            other => other,
+        };
+
+        PixelFormat {
+            internalformat,
+            ..format
+        }
+    }
+
+    /// Width and height of the base level, of every face for cube maps
+    pub fn size(&self) -> (u32, u32) {
+        match self.equirect_size {
+            Some(size) => (size, size),
+            None => self.images[0].dimensions(),
+        }
+    }
+
+    /// Array layers or 3D texture slices, 1 for 2D textures and cube maps
+    pub fn depth(&self) -> u32 {
+        match self.target {
+            gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D => self.images.len() as u32,
+            _ => 1,
+        }
+    }
+
     pub fn texture_storage(self, levels: i32) -> Self {
+        let (width, height) = self.size();
+
         unsafe {
-            gl::TextureStorage2D(
-                self.texture,
-                levels,
-                self.internalformat,
-                self.image.width() as i32,
-                self.image.height() as i32,
-            );
+            match self.target {
+                gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D => gl::TextureStorage3D(
+                    self.texture,
+                    levels,
+                    self.format.internalformat,
+                    width as i32,
+                    height as i32,
+                    self.depth() as i32,
+                ),
+                _ => gl::TextureStorage2D(
+                    self.texture,
+                    levels,
+                    self.format.internalformat,
+                    width as i32,
+                    height as i32,
+                ),
+            }
             self
         }
     }
 
+    /// Storage for the full mip chain of the image
+    pub fn mipmapped_storage(self) -> Self {
+        let levels = self.mip_levels();
+        self.texture_storage(levels)
+    }
+
+    /// Array layers keep their count, 3D textures halve their depth too
+    pub fn mip_levels(&self) -> i32 {
+        let (width, height) = self.size();
+
+        match self.target {
+            gl::TEXTURE_3D => mip_count(width.max(self.depth()), height),
+            _ => mip_count(width, height),
+        }
+    }
+
+    /// Uploads the base level, or renders it from the panorama of an `equirect_cube_map`
     pub fn sub_texture(self, x_offset: i32, y_offset: i32) -> Self {
-        unsafe {
-            gl::TextureSubImage2D(
+        if let Some(size) = self.equirect_size {
+            assert!(
+                (x_offset, y_offset) == (0, 0),
+                "Texture Error: cube maps rendered from a panorama are always uploaded whole"
+            );
+
+            // Linear, so the faces get the panorama's values as they are, sRGB encoded or not
+            let panorama = TextureBuilder::new(self.images[0].clone())
+                .texture_parameter_i(gl::TEXTURE_WRAP_S, gl::REPEAT)
+                .texture_parameter_i(gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE)
+                .texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::LINEAR)
+                .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
+                .texture_storage(1)
+                .sub_texture(0, 0)
+                .build();
+
+            ibl::equirect_to_cube(panorama, self.texture, size as i32);
+
+            unsafe {
+                gl::DeleteTextures(1, &panorama);
+            }
+            return self;
+        }
+
+        for (layer, image) in self.images.iter().enumerate() {
+            let layer = self.layer(layer);
+            upload(
                 self.texture,
                 0,
                 x_offset,
                 y_offset,
-                self.image.width() as i32,
-                self.image.height() as i32,
-                self.internal_format,
-                gl::UNSIGNED_BYTE,
-                self.image.as_bytes().as_ptr() as *const c_void,
+                layer,
+                image,
+                self.format,
+            );
+        }
+        self
+    }
+
+    /// Uploads a precomputed mip level of `layer`, the face, array layer or slice, 0 for 2D
+    /// textures. It has to be the level's size and the color type of the base image. Mipmaps of
+    /// 3D textures have fewer slices than the base level
+    pub fn sub_texture_level(self, level: i32, layer: usize, image: &DynamicImage) -> Self {
+        let (width, height) = self.size();
+        let size = |base: u32| (base >> level).max(1);
+        let expected = (size(width), size(height));
+
+        let layers = match self.target {
+            gl::TEXTURE_3D => size(self.depth()) as usize,
+            _ => self.images.len(),
+        };
+
+        assert!(
+            layer < layers && self.equirect_size.is_none(),
+            "Texture Error: no layer {layer} to upload mip level {level} to"
+        );
+        assert!(
+            image.dimensions() == expected,
+            "Texture Error: mip level {level} is {:?}, expected {expected:?}",
+            image.dimensions()
+        );
+        assert!(
+            image.color() == self.images[0].color(),
+            "Texture Error: mip level {level} is {:?}, the base level is {:?}",
+            image.color(),
+            self.images[0].color()
+        );
+
+        upload(
+            self.texture,
+            level,
+            0,
+            0,
+            self.layer(layer),
+            image,
+            self.format,
+        );
+        self
+    }
+
+    /// Fills every level below the base from it, after `sub_texture`. Array layers and cube
+    /// map faces are filtered separately
+    pub fn generate_mipmaps(self) -> Self {
+        unsafe {
+            gl::GenerateTextureMipmap(self.texture);
+        }
+        self
+    }
+
+    /// Linear filtering within and between mip levels
+    pub fn trilinear(self) -> Self {
+        self.texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR)
+            .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
+    }
+
+    /// Anisotropic filtering with up to `samples`, clamped to what the hardware supports
+    pub fn anisotropy(self, samples: f32) -> Self {
+        unsafe {
+            gl::TextureParameterf(
+                self.texture,
+                gl::TEXTURE_MAX_ANISOTROPY,
+                samples.clamp(1.0, max_anisotropy()),
             );
-            self
         }
+        self
+    }
+
+    /// The layer `TextureSubImage3D` needs for every target but `TEXTURE_2D`
+    fn layer(&self, index: usize) -> Option<i32> {
+        (self.target != gl::TEXTURE_2D).then_some(index as i32)
     }
 
     pub fn texture_parameter_i(self, p_name: gl::types::GLenum, param: gl::types::GLenum) -> Self {
@@ -67,7 +422,9 @@ impl TextureBuilder {
     }
 
     pub fn flip(mut self) -> Self {
-        self.image = self.image.flipv();
+        for image in &mut self.images {
+            *image = image.flipv();
+        }
         self
     }
 
@@ -76,30 +433,73 @@ impl TextureBuilder {
     }
 }
 
-pub(crate) struct TextureManager {
-    textures: Vec<u32>,
-}
+/// Panics unless every image has the size and color type of the first
+fn check_layers(kind: &str, images: &[DynamicImage]) {
+    let Some(first) = images.first() else {
+        panic!("Texture Error: needs at least one {kind}");
+    };
 
-impl TextureManager {
-    pub fn new() -> TextureManager {
-        TextureManager {
-            textures: Vec::new(),
-        }
+    for (index, image) in images.iter().enumerate() {
+        assert!(
+            image.dimensions() == first.dimensions() && image.color() == first.color(),
+            "Texture Error: {kind} {index} is {:?} {:?}, expected {:?} {:?} like the first",
+            image.dimensions(),
+            image.color(),
+            first.dimensions(),
+            first.color()
+        );
     }
+}
 
-    pub fn add_texture(&mut self, texture: u32) {
-        self.textures.push(texture);
-    }
+/// Uploads `image` to `level`, into one `layer` of cube map, array and 3D textures
+fn upload(
+    texture: u32,
+    level: i32,
+    x_offset: i32,
+    y_offset: i32,
+    layer: Option<i32>,
+    image: &DynamicImage,
+    format: PixelFormat,
+) {
+    let row_bytes = image.width() as usize * image.color().bytes_per_pixel() as usize;
+    let pixels = image.as_bytes().as_ptr() as *const c_void;
 
-    pub fn bind_textures(&self, first: u32) {
-        unsafe { gl::BindTextures(first, self.textures.len() as i32, self.textures.as_ptr()) }
-    }
-}
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, unpack_alignment(row_bytes));
 
-impl Index<usize> for TextureManager {
-    type Output = u32;
+        match layer {
+            None => gl::TextureSubImage2D(
+                texture,
+                level,
+                x_offset,
+                y_offset,
+                image.width() as i32,
+                image.height() as i32,
+                format.format,
+                format.pixel_type,
+                pixels,
+            ),
+            Some(layer) => gl::TextureSubImage3D(
+                texture,
+                level,
+                x_offset,
+                y_offset,
+                layer,
+                image.width() as i32,
+                image.height() as i32,
+                1,
+                format.format,
+                format.pixel_type,
+                pixels,
+            ),
+        }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.textures[index]
+        // Back to the GL default, other uploads assume it
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
     }
 }
+
+unsafe fn set_swizzle(texture: u32, swizzle: [gl::types::GLenum; 4]) {
+    let swizzle = swizzle.map(|channel| channel as i32);
+    gl::TextureParameteriv(texture, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
+}
@@ -0,0 +1,509 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use image::{DynamicImage, RgbaImage};
+use opengl::gl;
+
+use super::TextureBuilder;
+
+/// Where an image landed, in pixels of its page. The extruded border lies around the rectangle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRect {
+    /// Left, top, right and bottom texture coordinates. Pages are uploaded unflipped, so the
+    /// first image row is at v = 0
+    pub fn uv(&self, page_size: u32) -> [f32; 4] {
+        let size = page_size as f32;
+
+        [
+            self.x as f32 / size,
+            self.y as f32 / size,
+            (self.x + self.width) as f32 / size,
+            (self.y + self.height) as f32 / size,
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// The top edge of everything packed so far, as horizontal segments from left to right.
+/// Rectangles go where their top ends lowest
+#[derive(Clone, Debug)]
+struct Skyline {
+    width: u32,
+    height: u32,
+    segments: Vec<Segment>,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Skyline {
+        Skyline {
+            width,
+            height,
+            segments: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    /// The lowest y a rectangle starting at segment `index` can rest at
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.segments[index].x;
+        if x + width > self.width {
+            return None;
+        }
+
+        let mut y = 0;
+        let mut covered = 0;
+
+        for segment in &self.segments[index..] {
+            if covered >= width {
+                break;
+            }
+
+            y = y.max(segment.y);
+            if y + height > self.height {
+                return None;
+            }
+            covered += segment.width;
+        }
+
+        Some(y)
+    }
+
+    fn insert(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // Lowest top edge first, then the narrowest segment to keep wide gaps for wide images
+        let (index, y) = (0..self.segments.len())
+            .filter_map(|index| Some((index, self.fit(index, width, height)?)))
+            .min_by_key(|&(index, y)| (y + height, self.segments[index].width))?;
+
+        let x = self.segments[index].x;
+        self.segments.insert(
+            index,
+            Segment {
+                x,
+                y: y + height,
+                width,
+            },
+        );
+
+        // Segments under the new one are covered, partly or whole
+        let end = x + width;
+        let next = index + 1;
+        while next < self.segments.len() && self.segments[next].x < end {
+            let segment = &mut self.segments[next];
+            let segment_end = segment.x + segment.width;
+
+            if segment_end <= end {
+                self.segments.remove(next);
+            } else {
+                segment.width = segment_end - end;
+                segment.x = end;
+                break;
+            }
+        }
+
+        // Neighbours at the same height become one segment
+        self.segments.dedup_by(|right, left| {
+            let merge = left.y == right.y;
+            if merge {
+                left.width += right.width;
+            }
+            merge
+        });
+
+        Some((x, y))
+    }
+}
+
+/// Packs rectangles into square pages without GL, a new page starts when an image fits in
+/// none of the existing ones
+#[derive(Clone, Debug)]
+pub struct AtlasPacker {
+    page_size: u32,
+    padding: u32,
+    extrude: u32,
+    pages: Vec<Skyline>,
+}
+
+#[allow(dead_code)]
+impl AtlasPacker {
+    pub fn new(page_size: u32) -> AtlasPacker {
+        AtlasPacker {
+            page_size,
+            padding: 0,
+            extrude: 0,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Empty pixels between neighbouring images
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Pixels the edges of every image are repeated outwards, so filtering at the border
+    /// doesn't blend in the neighbours
+    pub fn with_extrude(mut self, extrude: u32) -> Self {
+        self.extrude = extrude;
+        self
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// `None` when the image and its border are larger than a page
+    pub fn pack(&mut self, width: u32, height: u32) -> Option<AtlasRect> {
+        let border = 2 * self.extrude;
+        if width + border > self.page_size || height + border > self.page_size {
+            return None;
+        }
+
+        // Padding follows every cell, the skyline is larger by it so the last cell of a row or
+        // column may still touch the page edge
+        let cell = (
+            width + border + self.padding,
+            height + border + self.padding,
+        );
+        let size = self.page_size + self.padding;
+
+        let placed = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(page, skyline)| Some((page, skyline.insert(cell.0, cell.1)?)));
+
+        let (page, (x, y)) = match placed {
+            Some(placed) => placed,
+            None => {
+                let mut skyline = Skyline::new(size, size);
+                let position = skyline.insert(cell.0, cell.1)?;
+                self.pages.push(skyline);
+                (self.pages.len() - 1, position)
+            }
+        };
+
+        Some(AtlasRect {
+            page,
+            x: x + self.extrude,
+            y: y + self.extrude,
+            width,
+            height,
+        })
+    }
+
+    /// Packs tallest first, which wastes less space, and returns the rectangles in the order
+    /// of `sizes`
+    pub fn pack_all(&mut self, sizes: &[(u32, u32)]) -> Vec<Option<AtlasRect>> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&index| {
+            let (width, height) = sizes[index];
+            (Reverse(height), Reverse(width))
+        });
+
+        let mut rects = vec![None; sizes.len()];
+        for index in order {
+            let (width, height) = sizes[index];
+            rects[index] = self.pack(width, height);
+        }
+        rects
+    }
+}
+
+/// Copies `image` into `page` at `rect`, repeating its edge pixels `extrude` pixels outwards
+fn blit(page: &mut RgbaImage, image: &RgbaImage, rect: AtlasRect, extrude: u32) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+
+    let extrude = extrude as i64;
+
+    for y in -extrude..rect.height as i64 + extrude {
+        for x in -extrude..rect.width as i64 + extrude {
+            let source_x = x.clamp(0, rect.width as i64 - 1) as u32;
+            let source_y = y.clamp(0, rect.height as i64 - 1) as u32;
+
+            page.put_pixel(
+                (rect.x as i64 + x) as u32,
+                (rect.y as i64 + y) as u32,
+                *image.get_pixel(source_x, source_y),
+            );
+        }
+    }
+}
+
+/// Collects named images for a `TextureAtlas`
+pub struct AtlasBuilder {
+    packer: AtlasPacker,
+    images: Vec<(String, RgbaImage)>,
+}
+
+#[allow(dead_code)]
+impl AtlasBuilder {
+    pub fn new(page_size: u32) -> AtlasBuilder {
+        AtlasBuilder {
+            packer: AtlasPacker::new(page_size),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.packer = self.packer.with_padding(padding);
+        self
+    }
+
+    pub fn extrude(mut self, extrude: u32) -> Self {
+        self.packer = self.packer.with_extrude(extrude);
+        self
+    }
+
+    /// Adding a name twice keeps the last image
+    pub fn add(mut self, name: &str, image: &DynamicImage) -> Self {
+        self.images.retain(|(existing, _)| existing != name);
+        self.images.push((name.to_string(), image.to_rgba8()));
+        self
+    }
+
+    /// Packs the images and uploads every page as an RGBA8 texture with linear filtering
+    pub fn build(mut self) -> TextureAtlas {
+        let sizes: Vec<(u32, u32)> = self
+            .images
+            .iter()
+            .map(|(_, image)| image.dimensions())
+            .collect();
+        let rects = self.packer.pack_all(&sizes);
+
+        let page_size = self.packer.page_size();
+        let mut pages = vec![RgbaImage::new(page_size, page_size); self.packer.pages()];
+        let mut regions = HashMap::new();
+
+        for ((name, image), rect) in self.images.iter().zip(rects) {
+            let Some(rect) = rect else {
+                panic!(
+                    "Atlas Error: `{name}` is {:?}, too large for pages of {page_size}",
+                    image.dimensions()
+                );
+            };
+
+            blit(&mut pages[rect.page], image, rect, self.packer.extrude);
+            regions.insert(name.clone(), rect);
+        }
+
+        let pages = pages
+            .into_iter()
+            .map(|page| {
+                TextureBuilder::new(DynamicImage::ImageRgba8(page))
+                    .texture_parameter_i(gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE)
+                    .texture_parameter_i(gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE)
+                    .texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::LINEAR)
+                    .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
+                    .texture_storage(1)
+                    .sub_texture(0, 0)
+                    .build()
+            })
+            .collect();
+
+        TextureAtlas {
+            page_size,
+            pages,
+            regions,
+        }
+    }
+}
+
+/// Images packed into one or more page textures, looked up by name
+pub struct TextureAtlas {
+    page_size: u32,
+    pages: Vec<u32>,
+    regions: HashMap<String, AtlasRect>,
+}
+
+#[allow(dead_code)]
+impl TextureAtlas {
+    pub fn pages(&self) -> &[u32] {
+        &self.pages
+    }
+
+    pub fn region(&self, name: &str) -> Option<AtlasRect> {
+        self.regions.get(name).copied()
+    }
+
+    /// The page texture and texture coordinates of `name`, see `AtlasRect::uv`
+    pub fn uv(&self, name: &str) -> Option<(u32, [f32; 4])> {
+        let rect = self.region(name)?;
+        Some((self.pages[rect.page], rect.uv(self.page_size)))
+    }
+}
+
+impl Drop for TextureAtlas {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(self.pages.len() as i32, self.pages.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn random_atlases_pack_every_image_on_its_page_without_overlaps() {
+        // xorshift, the atlases only have to be reproducible
+        let mut state = 0x9E37_79B9_u32;
+        let mut random = |min: u32, max: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            min + state % (max - min + 1)
+        };
+
+        for run in 0..20 {
+            let page_size = 1 << random(6, 9);
+            let padding = random(0, 4);
+            let extrude = random(0, 2);
+            let mut packer = AtlasPacker::new(page_size)
+                .with_padding(padding)
+                .with_extrude(extrude);
+
+            let sizes: Vec<(u32, u32)> = (0..random(1, 300))
+                .map(|_| (random(1, page_size / 4), random(1, page_size / 4)))
+                .collect();
+            let rects = packer.pack_all(&sizes);
+
+            // Each rectangle as the cell it claims on its page
+            let mut cells: Vec<(usize, [u32; 4])> = Vec::new();
+
+            for (index, rect) in rects.iter().enumerate() {
+                let rect = rect.unwrap_or_else(|| {
+                    panic!(
+                        "run {run}: image {index} of {:?} was not packed",
+                        sizes[index]
+                    )
+                });
+                assert_eq!((rect.width, rect.height), sizes[index], "run {run}");
+                assert!(rect.page < packer.pages(), "run {run}: {rect:?}");
+
+                let cell = [
+                    rect.x - extrude,
+                    rect.y - extrude,
+                    rect.x + rect.width + extrude,
+                    rect.y + rect.height + extrude,
+                ];
+                assert!(
+                    cell[2] <= page_size && cell[3] <= page_size,
+                    "run {run}: image {index} at {rect:?} leaves its page of {page_size}"
+                );
+
+                // Padding only has to separate neighbours, so it is added to one side
+                let padded = [cell[0], cell[1], cell[2] + padding, cell[3] + padding];
+                let overlap = cells.iter().find(|(page, other)| {
+                    *page == rect.page
+                        && padded[0] < other[2]
+                        && other[0] < padded[2]
+                        && padded[1] < other[3]
+                        && other[1] < padded[3]
+                });
+                assert!(
+                    overlap.is_none(),
+                    "run {run}: image {index} at {rect:?} overlaps {overlap:?}"
+                );
+
+                cells.push((rect.page, padded));
+            }
+        }
+    }
+
+    #[test]
+    fn padding_separates_neighbours_but_not_the_page_edge() {
+        let mut packer = AtlasPacker::new(10).with_padding(2);
+        let rects: Vec<_> = (0..4).map(|_| packer.pack(4, 4).unwrap()).collect();
+
+        let positions: Vec<_> = rects.iter().map(|rect| (rect.x, rect.y)).collect();
+        assert_eq!(positions, [(0, 0), (6, 0), (0, 6), (6, 6)]);
+        assert!(rects.iter().all(|rect| rect.page == 0));
+
+        // A fifth image only fits with the padding on a new page
+        assert_eq!(packer.pack(4, 4).unwrap().page, 1);
+    }
+
+    #[test]
+    fn extrude_offsets_the_rect_by_the_border() {
+        let mut packer = AtlasPacker::new(8).with_extrude(2);
+        assert_eq!(
+            packer.pack(4, 4),
+            Some(AtlasRect {
+                page: 0,
+                x: 2,
+                y: 2,
+                width: 4,
+                height: 4,
+            })
+        );
+
+        // The border counts against the page size
+        assert_eq!(AtlasPacker::new(8).with_extrude(1).pack(7, 7), None);
+    }
+
+    #[test]
+    fn blit_repeats_the_edge_pixels_by_extrude() {
+        let image = RgbaImage::from_fn(2, 3, |x, y| Rgba([x as u8, y as u8, 1, 255]));
+        let rect = AtlasRect {
+            page: 0,
+            x: 2,
+            y: 2,
+            width: 2,
+            height: 3,
+        };
+
+        let mut page = RgbaImage::new(8, 9);
+        blit(&mut page, &image, rect, 2);
+
+        for y in 0..9 {
+            for x in 0..8 {
+                let inside = (0..6).contains(&x) && (0..7).contains(&y);
+                let expected = if inside {
+                    *image.get_pixel(x.clamp(2, 3) - 2, y.clamp(2, 4) - 2)
+                } else {
+                    Rgba([0, 0, 0, 0])
+                };
+                assert_eq!(*page.get_pixel(x, y), expected, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn full_pages_grow_a_new_one_and_gaps_are_still_filled() {
+        let mut packer = AtlasPacker::new(8);
+
+        assert_eq!(packer.pack(8, 4).unwrap().page, 0);
+        assert_eq!(packer.pack(8, 8).unwrap().page, 1);
+        assert_eq!(packer.pages(), 2);
+
+        let rect = packer.pack(8, 4).unwrap();
+        assert_eq!((rect.page, rect.x, rect.y), (0, 0, 4));
+        assert_eq!(packer.pages(), 2);
+
+        assert_eq!(packer.pack(1, 1).unwrap().page, 2);
+        assert_eq!(packer.pack(9, 1), None);
+        assert_eq!(packer.pages(), 3);
+    }
+}
@@ -0,0 +1,212 @@
+use opengl::gl;
+
+use super::files::*;
+
+pub(super) const MAGIC: &[u8] = b"DDS ";
+
+// Offsets into the file, the 124 byte header follows the magic
+const FLAGS: usize = 8;
+const HEIGHT: usize = 12;
+const WIDTH: usize = 16;
+const MIP_MAP_COUNT: usize = 28;
+const PIXEL_FLAGS: usize = 80;
+const FOUR_CC: usize = 84;
+const RGB_BIT_COUNT: usize = 88;
+const RED_MASK: usize = 92;
+const ALPHA_MASK: usize = 104;
+const CAPS2: usize = 112;
+const DX10_HEADER: usize = 128;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+fn four_cc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// Formats identified by a FourCC code in the legacy header
+fn legacy_format(code: u32) -> Option<FileFormat> {
+    let format = match &code.to_le_bytes() {
+        b"DXT1" => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT1),
+        b"DXT2" | b"DXT3" => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT3),
+        b"DXT4" | b"DXT5" => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT5),
+        b"ATI1" | b"BC4U" => FileFormat::block(gl::COMPRESSED_RED_RGTC1),
+        b"BC4S" => FileFormat::block(gl::COMPRESSED_SIGNED_RED_RGTC1),
+        b"ATI2" | b"BC5U" => FileFormat::block(gl::COMPRESSED_RG_RGTC2),
+        b"BC5S" => FileFormat::block(gl::COMPRESSED_SIGNED_RG_RGTC2),
+        // D3DFMT_A16B16G16R16F and D3DFMT_A32B32G32R32F are stored as plain numbers
+        _ if code == 113 => FileFormat::pixels(gl::RGBA, gl::HALF_FLOAT, gl::RGBA16F, 8),
+        _ if code == 116 => FileFormat::pixels(gl::RGBA, gl::FLOAT, gl::RGBA32F, 16),
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+/// Maps a `DXGI_FORMAT` from the DX10 header
+fn dxgi_format(dxgi_format: u32) -> Option<FileFormat> {
+    let format = match dxgi_format {
+        2 => FileFormat::pixels(gl::RGBA, gl::FLOAT, gl::RGBA32F, 16),
+        10 => FileFormat::pixels(gl::RGBA, gl::HALF_FLOAT, gl::RGBA16F, 8),
+        28 => FileFormat::pixels(gl::RGBA, gl::UNSIGNED_BYTE, gl::RGBA8, 4),
+        29 => FileFormat::pixels(gl::RGBA, gl::UNSIGNED_BYTE, gl::SRGB8_ALPHA8, 4),
+        87 => FileFormat::pixels(gl::BGRA, gl::UNSIGNED_BYTE, gl::RGBA8, 4),
+        91 => FileFormat::pixels(gl::BGRA, gl::UNSIGNED_BYTE, gl::SRGB8_ALPHA8, 4),
+        71 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT1),
+        72 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT1),
+        74 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT3),
+        75 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT3),
+        77 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT5),
+        78 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT5),
+        80 => FileFormat::block(gl::COMPRESSED_RED_RGTC1),
+        81 => FileFormat::block(gl::COMPRESSED_SIGNED_RED_RGTC1),
+        83 => FileFormat::block(gl::COMPRESSED_RG_RGTC2),
+        84 => FileFormat::block(gl::COMPRESSED_SIGNED_RG_RGTC2),
+        95 => FileFormat::block(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT),
+        96 => FileFormat::block(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT),
+        98 => FileFormat::block(gl::COMPRESSED_RGBA_BPTC_UNORM),
+        99 => FileFormat::block(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM),
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+/// 32 bit RGBA or BGRA described by channel masks
+fn masked_format(bytes: &[u8]) -> Result<Option<FileFormat>, TextureFileError> {
+    let bit_count = read_u32(bytes, RGB_BIT_COUNT)?;
+    let red_mask = read_u32(bytes, RED_MASK)?;
+    let alpha_mask = read_u32(bytes, ALPHA_MASK)?;
+
+    let format = match (bit_count, red_mask, alpha_mask) {
+        (32, 0x0000_00FF, 0xFF00_0000) => Some(FileFormat::pixels(
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            gl::RGBA8,
+            4,
+        )),
+        (32, 0x00FF_0000, 0xFF00_0000) => Some(FileFormat::pixels(
+            gl::BGRA,
+            gl::UNSIGNED_BYTE,
+            gl::RGBA8,
+            4,
+        )),
+        _ => None,
+    };
+
+    Ok(format)
+}
+
+pub(super) fn parse(bytes: &[u8]) -> Result<TextureFile, TextureFileError> {
+    let width = read_u32(bytes, WIDTH)?;
+    let height = read_u32(bytes, HEIGHT)?;
+    let caps2 = read_u32(bytes, CAPS2)?;
+    let pixel_flags = read_u32(bytes, PIXEL_FLAGS)?;
+    let code = read_u32(bytes, FOUR_CC)?;
+
+    let levels = match read_u32(bytes, FLAGS)? & DDSD_MIPMAPCOUNT {
+        0 => 1,
+        _ => read_u32(bytes, MIP_MAP_COUNT)?.max(1),
+    };
+
+    let (format, layers, cube, data) = if pixel_flags & DDPF_FOURCC != 0 && code == four_cc(b"DX10")
+    {
+        let dxgi = read_u32(bytes, DX10_HEADER)?;
+        let dimension = read_u32(bytes, DX10_HEADER + 4)?;
+        let misc = read_u32(bytes, DX10_HEADER + 8)?;
+        let layers = read_u32(bytes, DX10_HEADER + 12)?;
+
+        if dimension != D3D10_RESOURCE_DIMENSION_TEXTURE2D {
+            return Err(TextureFileError::Unsupported(format!(
+                "DDS resource dimension {dimension}, only 2D textures can be loaded"
+            )));
+        }
+
+        let Some(format) = dxgi_format(dxgi) else {
+            return Err(TextureFileError::Unsupported(format!(
+                "DDS DXGI_FORMAT {dxgi}, only {SUPPORTED} can be loaded"
+            )));
+        };
+
+        let cube = misc & D3D10_RESOURCE_MISC_TEXTURECUBE != 0;
+        (format, layers, cube, DX10_HEADER + 20)
+    } else {
+        let format = if pixel_flags & DDPF_FOURCC != 0 {
+            legacy_format(code)
+        } else if pixel_flags & DDPF_RGB != 0 {
+            masked_format(bytes)?
+        } else {
+            None
+        };
+
+        let Some(format) = format else {
+            let name = match code.to_le_bytes() {
+                name if name.iter().all(u8::is_ascii_alphanumeric) => {
+                    String::from_utf8_lossy(&name).into_owned()
+                }
+                _ => code.to_string(),
+            };
+
+            return Err(TextureFileError::Unsupported(format!(
+                "DDS pixel format {name}, only {SUPPORTED} can be loaded"
+            )));
+        };
+
+        if caps2 & DDSCAPS2_VOLUME != 0 {
+            return Err(TextureFileError::Unsupported(
+                "DDS volume texture".to_string(),
+            ));
+        }
+
+        let cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+        if cube && caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+            return Err(TextureFileError::Unsupported(
+                "DDS cube map without all six faces".to_string(),
+            ));
+        }
+
+        (format, 1, cube, DX10_HEADER)
+    };
+
+    if levels > super::mip_count(width, height) as u32 {
+        return Err(TextureFileError::Invalid(format!(
+            "{levels} mip levels for {width}x{height}"
+        )));
+    }
+
+    let mut file = TextureFile {
+        format,
+        width,
+        height,
+        layers: layers.max(1),
+        faces: if cube { 6 } else { 1 },
+        levels: vec![Vec::new(); levels as usize],
+        generate_mipmaps: false,
+    };
+
+    // DDS stores every face with its whole mip chain, one after the other,
+    // so the levels are gathered from every image
+    let images = file.layers.checked_mul(file.faces).ok_or_else(|| {
+        TextureFileError::Invalid(format!("{} layers of {} faces", file.layers, file.faces))
+    })?;
+
+    let mut offset = data;
+    for _ in 0..images {
+        for (level, level_data) in file.levels.iter_mut().enumerate() {
+            let width = (width >> level).max(1);
+            let height = (height >> level).max(1);
+            let length = format.image_bytes(width, height)?;
+
+            level_data.extend_from_slice(slice(bytes, offset, length)?);
+            offset += length;
+        }
+    }
+
+    file.validate()
+}
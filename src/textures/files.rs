@@ -0,0 +1,668 @@
+use std::ffi::CStr;
+use std::fmt;
+
+use opengl::gl;
+
+use super::{dds, ktx2, mip_count, unpack_alignment, PixelFormat};
+use crate::assets;
+
+// BC1 to BC3 come from EXT_texture_compression_s3tc and EXT_texture_sRGB, which are not core
+pub(super) const COMPRESSED_RGB_S3TC_DXT1: gl::types::GLenum = 0x83F0;
+pub(super) const COMPRESSED_RGBA_S3TC_DXT1: gl::types::GLenum = 0x83F1;
+pub(super) const COMPRESSED_RGBA_S3TC_DXT3: gl::types::GLenum = 0x83F2;
+pub(super) const COMPRESSED_RGBA_S3TC_DXT5: gl::types::GLenum = 0x83F3;
+pub(super) const COMPRESSED_SRGB_S3TC_DXT1: gl::types::GLenum = 0x8C4C;
+pub(super) const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: gl::types::GLenum = 0x8C4D;
+pub(super) const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: gl::types::GLenum = 0x8C4E;
+pub(super) const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: gl::types::GLenum = 0x8C4F;
+
+const S3TC_EXTENSION: &str = "GL_EXT_texture_compression_s3tc";
+
+/// The formats both containers can hold, everything else is reported as unsupported
+pub(super) const SUPPORTED: &str = "BC1-BC7, RGBA8, BGRA8, RGBA16F and RGBA32F";
+
+#[derive(Debug)]
+pub(crate) enum TextureFileError {
+    Io(String),
+    /// Not a KTX2 or DDS file, or a header that contradicts itself
+    Invalid(String),
+    /// Shorter than its header says
+    Truncated {
+        needed: usize,
+        len: usize,
+    },
+    /// A valid file this loader or the driver can't handle
+    Unsupported(String),
+}
+
+impl fmt::Display for TextureFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureFileError::Io(error) => write!(f, "{error}"),
+            TextureFileError::Invalid(reason) => write!(f, "invalid file: {reason}"),
+            TextureFileError::Truncated { needed, len } => {
+                write!(f, "truncated file: needs {needed} bytes, has {len}")
+            }
+            TextureFileError::Unsupported(reason) => write!(f, "unsupported: {reason}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FileFormat {
+    /// 4x4 texel blocks of `block_bytes` each
+    Compressed {
+        internalformat: gl::types::GLenum,
+        block_bytes: usize,
+    },
+    Uncompressed {
+        format: PixelFormat,
+        pixel_bytes: usize,
+    },
+}
+
+#[allow(dead_code)]
+impl FileFormat {
+    /// BC1 and BC4 store 8 bytes per block, the others 16
+    pub(super) fn block(internalformat: gl::types::GLenum) -> FileFormat {
+        let block_bytes = match internalformat {
+            COMPRESSED_RGB_S3TC_DXT1
+            | COMPRESSED_RGBA_S3TC_DXT1
+            | COMPRESSED_SRGB_S3TC_DXT1
+            | COMPRESSED_SRGB_ALPHA_S3TC_DXT1
+            | gl::COMPRESSED_RED_RGTC1
+            | gl::COMPRESSED_SIGNED_RED_RGTC1 => 8,
+            _ => 16,
+        };
+
+        FileFormat::Compressed {
+            internalformat,
+            block_bytes,
+        }
+    }
+
+    pub(super) fn pixels(
+        format: gl::types::GLenum,
+        pixel_type: gl::types::GLenum,
+        internalformat: gl::types::GLenum,
+        pixel_bytes: usize,
+    ) -> FileFormat {
+        FileFormat::Uncompressed {
+            format: PixelFormat {
+                format,
+                pixel_type,
+                internalformat,
+            },
+            pixel_bytes,
+        }
+    }
+
+    pub fn internalformat(&self) -> gl::types::GLenum {
+        match self {
+            FileFormat::Compressed { internalformat, .. } => *internalformat,
+            FileFormat::Uncompressed { format, .. } => format.internalformat,
+        }
+    }
+
+    /// Bytes of one image of `width` x `height`, `Invalid` if that doesn't fit in memory
+    pub fn image_bytes(&self, width: u32, height: u32) -> Result<usize, TextureFileError> {
+        let (columns, rows, bytes) = match self {
+            FileFormat::Compressed { block_bytes, .. } => {
+                (width.div_ceil(4), height.div_ceil(4), *block_bytes)
+            }
+            FileFormat::Uncompressed { pixel_bytes, .. } => (width, height, *pixel_bytes),
+        };
+
+        (columns as usize)
+            .checked_mul(rows as usize)
+            .and_then(|count| count.checked_mul(bytes))
+            .ok_or_else(|| {
+                TextureFileError::Invalid(format!("{width}x{height} image is too large"))
+            })
+    }
+
+    fn is_s3tc(&self) -> bool {
+        matches!(
+            self.internalformat(),
+            COMPRESSED_RGB_S3TC_DXT1
+                | COMPRESSED_RGBA_S3TC_DXT1
+                | COMPRESSED_RGBA_S3TC_DXT3
+                | COMPRESSED_RGBA_S3TC_DXT5
+                | COMPRESSED_SRGB_S3TC_DXT1
+                | COMPRESSED_SRGB_ALPHA_S3TC_DXT1
+                | COMPRESSED_SRGB_ALPHA_S3TC_DXT3
+                | COMPRESSED_SRGB_ALPHA_S3TC_DXT5
+        )
+    }
+}
+
+/// Every level of a texture read from a KTX2 or DDS file, not yet on the GPU
+#[derive(Clone, Debug)]
+pub(crate) struct TextureFile {
+    pub format: FileFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Array layers, 1 for plain textures
+    pub layers: u32,
+    /// 6 for cube maps, otherwise 1
+    pub faces: u32,
+    /// Level 0 first. Every level holds its images tightly packed, faces within layers
+    pub levels: Vec<Vec<u8>>,
+    /// Only the base level is stored, `upload` generates the rest of the chain
+    pub generate_mipmaps: bool,
+}
+
+#[allow(dead_code)]
+impl TextureFile {
+    /// Reads an asset, the container is detected from its first bytes
+    pub fn load(path: &str) -> Result<TextureFile, TextureFileError> {
+        let bytes =
+            assets::read(path).map_err(|error| TextureFileError::Io(format!("{path}: {error}")))?;
+
+        TextureFile::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<TextureFile, TextureFileError> {
+        if bytes.starts_with(&ktx2::IDENTIFIER) {
+            ktx2::parse(bytes)
+        } else if bytes.starts_with(dds::MAGIC) {
+            dds::parse(bytes)
+        } else {
+            Err(TextureFileError::Invalid(
+                "neither a KTX2 nor a DDS file".to_string(),
+            ))
+        }
+    }
+
+    pub fn target(&self) -> gl::types::GLenum {
+        match (self.faces, self.layers) {
+            (6, 1) => gl::TEXTURE_CUBE_MAP,
+            (6, _) => gl::TEXTURE_CUBE_MAP_ARRAY,
+            (_, 1) => gl::TEXTURE_2D,
+            _ => gl::TEXTURE_2D_ARRAY,
+        }
+    }
+
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    /// Bytes `level` has to hold, for every face of every layer
+    pub fn level_bytes(&self, level: usize) -> Result<usize, TextureFileError> {
+        let (width, height) = self.level_size(level);
+        let too_large = || {
+            TextureFileError::Invalid(format!(
+                "{width}x{height} level with {} layers of {} faces is too large",
+                self.layers, self.faces
+            ))
+        };
+
+        let images = self.layers.checked_mul(self.faces).ok_or_else(too_large)?;
+        self.format
+            .image_bytes(width, height)?
+            .checked_mul(images as usize)
+            .ok_or_else(too_large)
+    }
+
+    /// Checks the size and level count every loader produces, before anything reaches GL
+    pub(super) fn validate(self) -> Result<TextureFile, TextureFileError> {
+        if self.width == 0 || self.height == 0 || self.layers == 0 {
+            return Err(TextureFileError::Invalid(format!(
+                "empty texture of {}x{} with {} layers",
+                self.width, self.height, self.layers
+            )));
+        }
+
+        if self.faces == 6 && self.width != self.height {
+            return Err(TextureFileError::Invalid(format!(
+                "cube map faces of {}x{} are not square",
+                self.width, self.height
+            )));
+        }
+
+        let max_levels = mip_count(self.width, self.height) as usize;
+        if self.levels.is_empty() || self.levels.len() > max_levels {
+            return Err(TextureFileError::Invalid(format!(
+                "{} mip levels for {}x{}, at most {max_levels} are possible",
+                self.levels.len(),
+                self.width,
+                self.height
+            )));
+        }
+
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = self.level_bytes(level)?;
+            if data.len() != expected {
+                return Err(TextureFileError::Invalid(format!(
+                    "mip level {level} has {} bytes, expected {expected}",
+                    data.len()
+                )));
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Creates an immutable texture with every level of the file, or the full chain generated from
+    /// the base level. BC1 to BC3 need the S3TC extension, drivers without it get an
+    /// `Unsupported` error instead of a GL error
+    pub fn upload(&self) -> Result<u32, TextureFileError> {
+        if self.format.is_s3tc() && !has_extension(S3TC_EXTENSION) {
+            return Err(TextureFileError::Unsupported(format!(
+                "BC1-BC3 textures need {S3TC_EXTENSION}"
+            )));
+        }
+
+        let target = self.target();
+        let levels = if self.generate_mipmaps {
+            mip_count(self.width, self.height)
+        } else {
+            self.levels.len() as i32
+        };
+        let depth = (self.layers * self.faces) as i32;
+        let internalformat = self.format.internalformat();
+
+        let mut texture = 0;
+
+        unsafe {
+            gl::CreateTextures(target, 1, &mut texture);
+
+            match target {
+                gl::TEXTURE_2D | gl::TEXTURE_CUBE_MAP => gl::TextureStorage2D(
+                    texture,
+                    levels,
+                    internalformat,
+                    self.width as i32,
+                    self.height as i32,
+                ),
+                _ => gl::TextureStorage3D(
+                    texture,
+                    levels,
+                    internalformat,
+                    self.width as i32,
+                    self.height as i32,
+                    depth,
+                ),
+            }
+
+            for (level, data) in self.levels.iter().enumerate() {
+                let (width, height) = self.level_size(level);
+                let (width, height) = (width as i32, height as i32);
+                let pixels = data.as_ptr() as *const _;
+
+                match self.format {
+                    FileFormat::Compressed { .. } if target == gl::TEXTURE_2D => {
+                        gl::CompressedTextureSubImage2D(
+                            texture,
+                            level as i32,
+                            0,
+                            0,
+                            width,
+                            height,
+                            internalformat,
+                            data.len() as i32,
+                            pixels,
+                        )
+                    }
+                    // Cube maps take their faces as layers with DSA
+                    FileFormat::Compressed { .. } => gl::CompressedTextureSubImage3D(
+                        texture,
+                        level as i32,
+                        0,
+                        0,
+                        0,
+                        width,
+                        height,
+                        depth,
+                        internalformat,
+                        data.len() as i32,
+                        pixels,
+                    ),
+                    FileFormat::Uncompressed {
+                        format,
+                        pixel_bytes,
+                    } => {
+                        let alignment = unpack_alignment(width as usize * pixel_bytes);
+                        gl::PixelStorei(gl::UNPACK_ALIGNMENT, alignment);
+
+                        if target == gl::TEXTURE_2D {
+                            gl::TextureSubImage2D(
+                                texture,
+                                level as i32,
+                                0,
+                                0,
+                                width,
+                                height,
+                                format.format,
+                                format.pixel_type,
+                                pixels,
+                            );
+                        } else {
+                            gl::TextureSubImage3D(
+                                texture,
+                                level as i32,
+                                0,
+                                0,
+                                0,
+                                width,
+                                height,
+                                depth,
+                                format.format,
+                                format.pixel_type,
+                                pixels,
+                            );
+                        }
+
+                        // Back to the GL default, other uploads assume it
+                        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+                    }
+                }
+            }
+
+            if self.generate_mipmaps {
+                gl::GenerateTextureMipmap(texture);
+            }
+
+            let min_filter = if levels > 1 {
+                gl::LINEAR_MIPMAP_LINEAR
+            } else {
+                gl::LINEAR
+            };
+            gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        }
+
+        Ok(texture)
+    }
+}
+
+/// Little endian reads that fail with `Truncated` instead of panicking
+pub(super) fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, TextureFileError> {
+    let data = slice(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes(data.try_into().unwrap()))
+}
+
+pub(super) fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, TextureFileError> {
+    let data = slice(bytes, offset, 8)?;
+    Ok(u64::from_le_bytes(data.try_into().unwrap()))
+}
+
+pub(super) fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], TextureFileError> {
+    let needed = offset.saturating_add(len);
+
+    bytes
+        .get(offset..needed)
+        .ok_or(TextureFileError::Truncated {
+            needed,
+            len: bytes.len(),
+        })
+}
+
+fn has_extension(name: &str) -> bool {
+    let mut count = 0;
+
+    unsafe {
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+
+        (0..count as u32).any(|index| {
+            let extension = gl::GetStringi(gl::EXTENSIONS, index);
+            !extension.is_null()
+                && CStr::from_ptr(extension as *const _).to_bytes() == name.as_bytes()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+    const VK_FORMAT_BC1_RGB_UNORM_BLOCK: u32 = 131;
+    const VK_FORMAT_BC7_UNORM_BLOCK: u32 = 145;
+    const DXGI_FORMAT_BC7_UNORM: u32 = 98;
+
+    /// A KTX2 file with the given header fields and one buffer per level
+    fn ktx2(
+        vk_format: u32,
+        [width, height]: [u32; 2],
+        [layers, faces]: [u32; 2],
+        levels: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut bytes = ktx2::IDENTIFIER.to_vec();
+        let fields = [
+            vk_format,
+            1,
+            width,
+            height,
+            0,
+            layers,
+            faces,
+            levels.len() as u32,
+            0,
+        ];
+        for field in fields.iter().chain(&[0; 4]) {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 16]);
+
+        let mut offset = bytes.len() + levels.len() * 24;
+        for level in levels {
+            for value in [offset, level.len(), level.len()] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+
+        for level in levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    /// A DDS header of `width` x `height` with `levels` mip levels, the caller fills in the
+    /// pixel format
+    fn dds_header([width, height]: [u32; 2], levels: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(b"DDS ");
+
+        set_u32(&mut bytes, 4, 124);
+        set_u32(&mut bytes, 8, 0x1007 | 0x20000);
+        set_u32(&mut bytes, 12, height);
+        set_u32(&mut bytes, 16, width);
+        set_u32(&mut bytes, 28, levels);
+        set_u32(&mut bytes, 76, 32);
+        bytes
+    }
+
+    fn set_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn four_cc(bytes: &mut [u8], code: &[u8; 4]) {
+        set_u32(bytes, 80, 0x4);
+        bytes[84..88].copy_from_slice(code);
+    }
+
+    fn dx10(bytes: &mut Vec<u8>, dxgi_format: u32, misc: u32, layers: u32) {
+        four_cc(bytes, b"DX10");
+        for value in [dxgi_format, 3, misc, layers, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn ktx2_bc1_mip_chain() {
+        let levels = vec![vec![1; 32], vec![2; 8], vec![3; 8], vec![4; 8]];
+        let file = TextureFile::parse(&ktx2(
+            VK_FORMAT_BC1_RGB_UNORM_BLOCK,
+            [8, 8],
+            [0, 1],
+            &levels,
+        ))
+        .unwrap();
+
+        assert_eq!(file.format, FileFormat::block(COMPRESSED_RGB_S3TC_DXT1));
+        assert_eq!(
+            (file.width, file.height, file.layers, file.faces),
+            (8, 8, 1, 1)
+        );
+        assert_eq!(file.target(), gl::TEXTURE_2D);
+        assert_eq!(file.levels, levels);
+    }
+
+    #[test]
+    fn ktx2_bc7_uses_16_byte_blocks() {
+        let levels = vec![vec![7; 6 * 16]];
+        let file =
+            TextureFile::parse(&ktx2(VK_FORMAT_BC7_UNORM_BLOCK, [12, 5], [0, 1], &levels)).unwrap();
+
+        assert_eq!(file.format.internalformat(), gl::COMPRESSED_RGBA_BPTC_UNORM);
+        assert_eq!(file.level_bytes(0).unwrap(), 6 * 16);
+    }
+
+    #[test]
+    fn ktx2_level_count_0_generates_mip_maps_for_pixels_only() {
+        let mut bytes = ktx2(VK_FORMAT_R8G8B8A8_UNORM, [4, 4], [0, 1], &[vec![0; 64]]);
+        set_u32(&mut bytes, ktx2::IDENTIFIER.len() + 7 * 4, 0);
+        let file = TextureFile::parse(&bytes).unwrap();
+        assert!(file.generate_mipmaps);
+        assert_eq!(file.levels.len(), 1);
+
+        let mut bytes = ktx2(VK_FORMAT_BC7_UNORM_BLOCK, [4, 4], [0, 1], &[vec![0; 16]]);
+        set_u32(&mut bytes, ktx2::IDENTIFIER.len() + 7 * 4, 0);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn ktx2_cube_map_and_array() {
+        let cube = vec![vec![0; 6 * 4 * 4 * 4]];
+        let file =
+            TextureFile::parse(&ktx2(VK_FORMAT_R8G8B8A8_UNORM, [4, 4], [0, 6], &cube)).unwrap();
+        assert_eq!((file.layers, file.faces), (1, 6));
+        assert_eq!(file.target(), gl::TEXTURE_CUBE_MAP);
+
+        let array = vec![vec![0; 3 * 2 * 2 * 4], vec![0; 3 * 4]];
+        let file =
+            TextureFile::parse(&ktx2(VK_FORMAT_R8G8B8A8_UNORM, [2, 2], [3, 1], &array)).unwrap();
+        assert_eq!((file.layers, file.faces), (3, 1));
+        assert_eq!(file.target(), gl::TEXTURE_2D_ARRAY);
+    }
+
+    #[test]
+    fn dds_bc7_array() {
+        let mut bytes = dds_header([4, 4], 1);
+        dx10(&mut bytes, DXGI_FORMAT_BC7_UNORM, 0, 2);
+        bytes.extend_from_slice(&[1; 16]);
+        bytes.extend_from_slice(&[2; 16]);
+
+        let file = TextureFile::parse(&bytes).unwrap();
+        assert_eq!(file.format.internalformat(), gl::COMPRESSED_RGBA_BPTC_UNORM);
+        assert_eq!(file.target(), gl::TEXTURE_2D_ARRAY);
+        assert_eq!(file.levels, vec![[[1; 16], [2; 16]].concat()]);
+    }
+
+    #[test]
+    fn dds_cube_map_gathers_levels_from_every_face() {
+        let mut bytes = dds_header([8, 8], 2);
+        four_cc(&mut bytes, b"DXT1");
+        set_u32(&mut bytes, 112, 0x200 | 0xFC00);
+
+        // Every face stores its whole mip chain: 4 blocks, then 1
+        for face in 0..6 {
+            bytes.extend_from_slice(&[face; 32]);
+            bytes.extend_from_slice(&[face + 10; 8]);
+        }
+
+        let file = TextureFile::parse(&bytes).unwrap();
+        assert_eq!(file.format, FileFormat::block(COMPRESSED_RGBA_S3TC_DXT1));
+        assert_eq!(file.target(), gl::TEXTURE_CUBE_MAP);
+        assert_eq!(
+            file.levels[0],
+            (0..6).flat_map(|face| [face; 32]).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            file.levels[1],
+            (10..16).flat_map(|face| [face; 8]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn truncated_files() {
+        let mut bytes = ktx2(
+            VK_FORMAT_BC1_RGB_UNORM_BLOCK,
+            [8, 8],
+            [0, 1],
+            &[vec![0; 32]],
+        );
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Truncated { .. })
+        ));
+
+        let mut bytes = dds_header([8, 8], 1);
+        four_cc(&mut bytes, b"DXT5");
+        bytes.extend_from_slice(&[0; 63]);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Truncated {
+                needed: 192,
+                len: 191
+            })
+        ));
+
+        assert!(matches!(
+            TextureFile::parse(&ktx2::IDENTIFIER),
+            Err(TextureFileError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        let bytes = ktx2(1000, [4, 4], [0, 1], &[vec![0; 64]]);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Unsupported(_))
+        ));
+
+        let mut bytes = dds_header([4, 4], 1);
+        four_cc(&mut bytes, b"ETC1");
+        bytes.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Unsupported(_))
+        ));
+
+        assert!(matches!(
+            TextureFile::parse(b"\x89PNG\r\n\x1a\n"),
+            Err(TextureFileError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn huge_headers_are_invalid_instead_of_overflowing() {
+        let mut bytes = ktx2(VK_FORMAT_R8G8B8A8_UNORM, [u32::MAX, u32::MAX], [0, 1], &[]);
+        bytes.resize(100, 0);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Invalid(_))
+        ));
+
+        let mut bytes = ktx2(VK_FORMAT_R8G8B8A8_UNORM, [4, 4], [u32::MAX, 6], &[]);
+        bytes.resize(100, 0);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Invalid(_))
+        ));
+
+        let mut bytes = dds_header([4, 4], 1);
+        dx10(&mut bytes, DXGI_FORMAT_BC7_UNORM, 0x4, u32::MAX);
+        assert!(matches!(
+            TextureFile::parse(&bytes),
+            Err(TextureFileError::Invalid(_))
+        ));
+    }
+}
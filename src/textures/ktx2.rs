@@ -0,0 +1,130 @@
+use opengl::gl;
+
+use super::files::*;
+
+pub(super) const IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+
+/// The level index starts after the identifier, nine header fields and the data format,
+/// key/value and supercompression ranges
+const LEVEL_INDEX: usize = 80;
+
+/// Maps a `VkFormat` to the GL format, `None` for formats we can't upload
+fn format(vk_format: u32) -> Option<FileFormat> {
+    let format = match vk_format {
+        37 => FileFormat::pixels(gl::RGBA, gl::UNSIGNED_BYTE, gl::RGBA8, 4),
+        43 => FileFormat::pixels(gl::RGBA, gl::UNSIGNED_BYTE, gl::SRGB8_ALPHA8, 4),
+        44 => FileFormat::pixels(gl::BGRA, gl::UNSIGNED_BYTE, gl::RGBA8, 4),
+        50 => FileFormat::pixels(gl::BGRA, gl::UNSIGNED_BYTE, gl::SRGB8_ALPHA8, 4),
+        97 => FileFormat::pixels(gl::RGBA, gl::HALF_FLOAT, gl::RGBA16F, 8),
+        109 => FileFormat::pixels(gl::RGBA, gl::FLOAT, gl::RGBA32F, 16),
+        131 => FileFormat::block(COMPRESSED_RGB_S3TC_DXT1),
+        132 => FileFormat::block(COMPRESSED_SRGB_S3TC_DXT1),
+        133 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT1),
+        134 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT1),
+        135 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT3),
+        136 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT3),
+        137 => FileFormat::block(COMPRESSED_RGBA_S3TC_DXT5),
+        138 => FileFormat::block(COMPRESSED_SRGB_ALPHA_S3TC_DXT5),
+        139 => FileFormat::block(gl::COMPRESSED_RED_RGTC1),
+        140 => FileFormat::block(gl::COMPRESSED_SIGNED_RED_RGTC1),
+        141 => FileFormat::block(gl::COMPRESSED_RG_RGTC2),
+        142 => FileFormat::block(gl::COMPRESSED_SIGNED_RG_RGTC2),
+        143 => FileFormat::block(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT),
+        144 => FileFormat::block(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT),
+        145 => FileFormat::block(gl::COMPRESSED_RGBA_BPTC_UNORM),
+        146 => FileFormat::block(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM),
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+pub(super) fn parse(bytes: &[u8]) -> Result<TextureFile, TextureFileError> {
+    let header = |field: usize| read_u32(bytes, IDENTIFIER.len() + field * 4);
+
+    let vk_format = header(0)?;
+    let width = header(2)?;
+    let height = header(3)?;
+    let depth = header(4)?;
+    let layers = header(5)?;
+    let faces = header(6)?;
+    // 0 asks the loader to generate mip maps, the file itself only stores the base level
+    let level_count = header(7)?;
+    let supercompression = header(8)?;
+
+    if supercompression != 0 {
+        let scheme = match supercompression {
+            1 => "BasisLZ",
+            2 => "Zstandard",
+            3 => "ZLIB",
+            _ => "unknown",
+        };
+
+        return Err(TextureFileError::Unsupported(format!(
+            "KTX2 supercompression scheme {supercompression} ({scheme})"
+        )));
+    }
+
+    let Some(format) = format(vk_format) else {
+        return Err(TextureFileError::Unsupported(format!(
+            "KTX2 VkFormat {vk_format}, only {SUPPORTED} can be loaded"
+        )));
+    };
+
+    if depth > 1 {
+        return Err(TextureFileError::Unsupported(format!(
+            "KTX2 3D texture with depth {depth}"
+        )));
+    }
+
+    if faces != 1 && faces != 6 {
+        return Err(TextureFileError::Invalid(format!(
+            "{faces} faces, KTX2 allows 1 or 6"
+        )));
+    }
+
+    // GL can't generate the levels of block compressed textures
+    let generate_mipmaps = level_count == 0;
+    if generate_mipmaps && matches!(format, FileFormat::Compressed { .. }) {
+        return Err(TextureFileError::Unsupported(
+            "KTX2 level count 0 with a block compressed format, its mip maps can't be generated"
+                .to_string(),
+        ));
+    }
+
+    let levels = level_count.max(1);
+    if levels > super::mip_count(width, height) as u32 {
+        return Err(TextureFileError::Invalid(format!(
+            "{levels} mip levels for {width}x{height}"
+        )));
+    }
+
+    let mut file = TextureFile {
+        format,
+        width,
+        height,
+        layers: layers.max(1),
+        faces,
+        levels: Vec::new(),
+        generate_mipmaps,
+    };
+
+    for level in 0..levels as usize {
+        let entry = LEVEL_INDEX + level * 24;
+        let offset = read_u64(bytes, entry)? as usize;
+        let length = read_u64(bytes, entry + 8)? as usize;
+
+        let expected = file.level_bytes(level)?;
+        if length != expected {
+            return Err(TextureFileError::Invalid(format!(
+                "KTX2 mip level {level} has {length} bytes, expected {expected}"
+            )));
+        }
+
+        file.levels.push(slice(bytes, offset, length)?.to_vec());
+    }
+
+    file.validate()
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use opengl::gl;
+
+use super::{TextureBuilder, TextureFile};
+use crate::assets;
+use crate::samplers::SamplerHandle;
+
+/// How `TextureManager::load_with` builds a texture, the same path with other options is
+/// another texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TextureOptions {
+    pub srgb: bool,
+    pub flip: bool,
+    /// A full mip chain with trilinear filtering, otherwise one level with linear filtering
+    pub mipmaps: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            srgb: false,
+            flip: false,
+            mipmaps: true,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl TextureOptions {
+    /// For color textures, sampling returns linear colors. 16 bit and float images fail to load
+    /// with it, they are linear already
+    pub fn srgb(mut self) -> Self {
+        self.srgb = true;
+        self
+    }
+
+    pub fn flipped(mut self) -> Self {
+        self.flip = true;
+        self
+    }
+
+    pub fn without_mipmaps(mut self) -> Self {
+        self.mipmaps = false;
+        self
+    }
+}
+
+/// Owns the GL texture, deleted with the last handle
+#[derive(Debug)]
+struct Texture {
+    id: u32,
+    name: String,
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+/// A texture from a `TextureManager` or `TextureHandle::new`. Clones share the texture, it
+/// stays loaded as long as any of them lives
+#[derive(Clone, Debug)]
+pub(crate) struct TextureHandle(Rc<Texture>);
+
+#[allow(dead_code)]
+impl TextureHandle {
+    /// Takes ownership of a texture created elsewhere, deleted with the last handle
+    pub fn new(id: u32, name: &str) -> TextureHandle {
+        TextureHandle(Rc::new(Texture {
+            id,
+            name: name.to_string(),
+        }))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// The asset path or the name it was inserted with
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            gl::BindTextureUnit(unit, self.0.id);
+        }
+    }
+}
+
+impl PartialEq for TextureHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TextureHandle {}
+
+/// Textures and their samplers bound to consecutive units with one call each. Holds the
+/// handles, so the textures and samplers live as long as the bindings
+#[derive(Clone, Default)]
+pub(crate) struct TextureBindings {
+    handles: Vec<TextureHandle>,
+    sampler_handles: Vec<Option<SamplerHandle>>,
+    textures: Vec<u32>,
+    /// Sampler of every texture, 0 samples with the texture's own parameters
+    samplers: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl TextureBindings {
+    pub fn new() -> TextureBindings {
+        TextureBindings::default()
+    }
+
+    pub fn add(&mut self, texture: &TextureHandle) {
+        self.handles.push(texture.clone());
+        self.sampler_handles.push(None);
+        self.textures.push(texture.id());
+        self.samplers.push(0);
+    }
+
+    pub fn add_with_sampler(&mut self, texture: &TextureHandle, sampler: &SamplerHandle) {
+        self.add(texture);
+        self.set_sampler(self.len() - 1, sampler);
+    }
+
+    pub fn with(mut self, texture: &TextureHandle) -> Self {
+        self.add(texture);
+        self
+    }
+
+    pub fn with_sampler(mut self, texture: &TextureHandle, sampler: &SamplerHandle) -> Self {
+        self.add_with_sampler(texture, sampler);
+        self
+    }
+
+    pub fn set_sampler(&mut self, index: usize, sampler: &SamplerHandle) {
+        self.sampler_handles[index] = Some(sampler.clone());
+        self.samplers[index] = sampler.id();
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Binds the textures and their samplers to consecutive units from `first`
+    pub fn bind(&self, first: u32) {
+        unsafe {
+            gl::BindTextures(first, self.textures.len() as i32, self.textures.as_ptr());
+            gl::BindSamplers(first, self.samplers.len() as i32, self.samplers.as_ptr());
+        }
+    }
+}
+
+/// Textures by asset path or name. The same request returns the texture that is still loaded
+/// instead of loading it again, and missing or broken files get a checkerboard
+pub(crate) struct TextureManager {
+    textures: HashMap<(String, TextureOptions), Weak<Texture>>,
+    fallback: TextureHandle,
+}
+
+#[allow(dead_code)]
+impl TextureManager {
+    pub fn new() -> TextureManager {
+        TextureManager {
+            textures: HashMap::new(),
+            fallback: checkerboard(),
+        }
+    }
+
+    pub fn load(&mut self, path: &str) -> TextureHandle {
+        self.load_with(path, TextureOptions::default())
+    }
+
+    /// Images go through `TextureBuilder`. `.ktx2` and `.dds` files keep their own format and
+    /// mip levels, the options don't apply to them
+    pub fn load_with(&mut self, path: &str, options: TextureOptions) -> TextureHandle {
+        let key = (path.to_string(), options);
+
+        if let Some(texture) = self.textures.get(&key).and_then(Weak::upgrade) {
+            return TextureHandle(texture);
+        }
+
+        match load(path, options) {
+            Ok(id) => self.insert_with(key, id),
+            Err(error) => {
+                println!(
+                    "Warning: texture `{path}` failed to load ({error}), using a checkerboard"
+                );
+                self.fallback.clone()
+            }
+        }
+    }
+
+    /// Takes ownership of a texture created elsewhere, `load` finds it under `name` with the
+    /// default options
+    pub fn insert(&mut self, name: &str, texture: u32) -> TextureHandle {
+        self.insert_with((name.to_string(), TextureOptions::default()), texture)
+    }
+
+    /// The texture loaded for `name` with the default options, if any handle still holds it
+    pub fn get(&self, name: &str) -> Option<TextureHandle> {
+        let key = (name.to_string(), TextureOptions::default());
+        self.textures
+            .get(&key)
+            .and_then(Weak::upgrade)
+            .map(TextureHandle)
+    }
+
+    /// The checkerboard missing textures are replaced with
+    pub fn fallback(&self) -> TextureHandle {
+        self.fallback.clone()
+    }
+
+    fn insert_with(&mut self, key: (String, TextureOptions), id: u32) -> TextureHandle {
+        // Entries whose last handle dropped have already deleted their texture
+        self.textures
+            .retain(|_, texture| texture.strong_count() > 0);
+
+        let texture = TextureHandle::new(id, &key.0);
+        self.textures.insert(key, Rc::downgrade(&texture.0));
+        texture
+    }
+}
+
+fn load(path: &str, options: TextureOptions) -> Result<u32, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str());
+
+    if matches!(extension, Some("ktx2" | "dds")) {
+        return TextureFile::load(path)
+            .and_then(|file| file.upload())
+            .map_err(|error| error.to_string());
+    }
+
+    let image = assets::image(path).map_err(|error| error.to_string())?;
+    let builder = TextureBuilder::new(image).try_srgb(options.srgb)?;
+    let builder = if options.flip {
+        builder.flip()
+    } else {
+        builder
+    };
+
+    let texture = if options.mipmaps {
+        builder
+            .trilinear()
+            .mipmapped_storage()
+            .sub_texture(0, 0)
+            .generate_mipmaps()
+            .build()
+    } else {
+        builder
+            .texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::LINEAR)
+            .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
+            .texture_storage(1)
+            .sub_texture(0, 0)
+            .build()
+    };
+
+    Ok(texture)
+}
+
+/// Magenta and black squares, hard to mistake for a real texture
+fn checkerboard() -> TextureHandle {
+    let image = RgbaImage::from_fn(64, 64, |x, y| match (x / 8 + y / 8) % 2 {
+        0 => Rgba([255, 0, 255, 255]),
+        _ => Rgba([0, 0, 0, 255]),
+    });
+
+    let id = TextureBuilder::new(DynamicImage::ImageRgba8(image))
+        .texture_parameter_i(gl::TEXTURE_MIN_FILTER, gl::NEAREST)
+        .texture_parameter_i(gl::TEXTURE_MAG_FILTER, gl::NEAREST)
+        .texture_storage(1)
+        .sub_texture(0, 0)
+        .build();
+
+    TextureHandle::new(id, "fallback checkerboard")
+}